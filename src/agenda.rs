@@ -0,0 +1,115 @@
+use chrono::*;
+
+use error::Result;
+use models::{Task, TaskOrder};
+use period::Period;
+use store::Store;
+
+/// Where a task falls in the agenda, by its due date. Weeks end on Sunday, as in `Period`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AgendaGroup {
+    Overdue,
+    Today,
+    ThisWeek,
+    Later,
+}
+
+impl AgendaGroup {
+    pub fn of(due_date: NaiveDate, today: Date<Local>) -> AgendaGroup {
+        let week_end = Period::named("this-week", today).map(|week| week.to).unwrap_or(today);
+        if due_date < today.naive_local() {
+            AgendaGroup::Overdue
+        } else if due_date == today.naive_local() {
+            AgendaGroup::Today
+        } else if due_date < week_end.naive_local() {
+            AgendaGroup::ThisWeek
+        } else {
+            AgendaGroup::Later
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            AgendaGroup::Overdue => "overdue",
+            AgendaGroup::Today => "today",
+            AgendaGroup::ThisWeek => "this-week",
+            AgendaGroup::Later => "later",
+        }
+    }
+}
+
+/// An open task with a due date
+#[derive(Debug, Serialize)]
+pub struct AgendaEntry {
+    #[serde(serialize_with = "serialize_group")]
+    pub group: AgendaGroup,
+    pub task: Task,
+}
+
+fn serialize_group<S: ::serde::Serializer>(group: &AgendaGroup,
+                                            serializer: S)
+                                            -> ::std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_str(group.name())
+}
+
+/// The open tasks that have a due date, by group, then by priority as in `TaskOrder::Priority`.
+pub fn agenda(store: &dyn Store, today: Date<Local>) -> Result<Vec<AgendaEntry>> {
+    let mut tasks: Vec<Task> = Task::all(store)?
+        .into_iter()
+        .filter(|task| task.open && task.due_date.is_some())
+        .collect();
+    tasks.sort_by(|a, b| TaskOrder::Priority.compare(a, b));
+
+    let mut result: Vec<AgendaEntry> = tasks.into_iter()
+        .filter_map(|task| {
+            task.due_date.map(|due_date| {
+                AgendaEntry {
+                    group: AgendaGroup::of(due_date, today),
+                    task,
+                }
+            })
+        })
+        .collect();
+    // a stable sort keeps the priority order within each group
+    result.sort_by_key(|entry| entry.group);
+    Ok(result)
+}
+#[test]
+fn test_agenda() {
+    use store::memory::MemoryStore;
+
+    // a Wednesday
+    let today = Local.ymd(2017, 2, 15);
+    let store = MemoryStore::new();
+    let create = |title, priority, day: Option<u32>| {
+        let mut task = Task::new(None, title, "", Local::now());
+        task.priority = priority;
+        task.due_date = day.map(|day| NaiveDate::from_ymd(2017, 2, day));
+        task.create(&store).unwrap()
+    };
+    create("undated", Some(1), None);
+    create("sunday", Some(2), Some(19));
+    create("monday", Some(1), Some(20));
+    create("friday", Some(3), Some(17));
+    create("yesterday", None, Some(14));
+    create("today", None, Some(15));
+    create("thursday", Some(1), Some(16));
+    let done = create("done", None, Some(15));
+    Task::finish(&store, done.id).unwrap();
+
+    let entries: Vec<(&str, String)> = agenda(&store, today)
+        .unwrap()
+        .into_iter()
+        .map(|entry| (entry.group.name(), entry.task.title))
+        .collect();
+    let expected = vec![("overdue", "yesterday"),
+                        ("today", "today"),
+                        ("this-week", "thursday"),
+                        ("this-week", "sunday"),
+                        ("this-week", "friday"),
+                        ("later", "monday")];
+    assert_eq!(entries,
+               expected.into_iter()
+                   .map(|(group, title)| (group, title.to_owned()))
+                   .collect::<Vec<_>>());
+}
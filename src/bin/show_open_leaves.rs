@@ -1,13 +1,13 @@
 extern crate todo;
 
 use self::todo::connect_db;
-use self::todo::models::Task;
+use self::todo::models::{Task, TaskOrder};
 
 pub fn main() {
-    let conn = connect_db();
+    let conn = connect_db().unwrap();
 
     println!("Start searching for open leavds...");
-    for task in Task::open_leaves(&conn) {
+    for task in Task::open_leaves(&*conn, TaskOrder::Created).unwrap() {
         println!("{:?}", task);
     }
     println!("Done");
@@ -4,16 +4,16 @@ use self::todo::connect_db;
 use self::todo::models::Task;
 
 pub fn main() {
-    let conn = connect_db();
+    let conn = connect_db().unwrap();
 
     println!("Start searching for tasks...");
-    for task in Task::all(&conn) {
+    for task in Task::all(&*conn).unwrap() {
         println!("{:?}", task);
     }
     println!("stop searching for tasks...");
 
     println!("Start searching for notes...");
-    for note in Task::find_notes(&conn, 2) {
+    for note in Task::find_notes(&*conn, 2).unwrap() {
         println!("{:?}", note);
     }
     println!("stop searching for notes...");
@@ -1,14 +1,16 @@
 extern crate todo;
 extern crate chrono;
+extern crate postgres;
 
-use self::todo::connect_db;
-use self::todo::models::{Task, Note};
 use chrono::*;
+use postgres::{Connection, TlsMode};
 
 pub fn main() {
     let now = Local::now();
 
-    let conn = connect_db();
+    // This pokes at Postgres directly, so it does not go through `connect_db()`
+    let db_url = std::env::var("DATABASE_URL").expect("$DATABASE_URL is not set");
+    let conn = Connection::connect(db_url, TlsMode::None).unwrap();
 
     conn.execute("
     CREATE TABLE IF NOT EXISTS test (
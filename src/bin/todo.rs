@@ -2,37 +2,59 @@ extern crate todo;
 extern crate clap;
 extern crate mktemp;
 extern crate chrono;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
 
 use std::str::FromStr;
 use std::io;
 use std::clone::Clone;
 
-use self::todo::connect_db;
-use self::todo::models::{Task, Note, NoteAux, Review, Template, create_tables};
+use self::todo::{connect_db, Error, Result, Store};
+use self::todo::store::memory::MemoryStore;
+use self::todo::migrations::{self, Direction};
+use self::todo::agenda;
+use self::todo::models::{Task, TaskAux, Note, NoteAux, Review, Template, Timer, SearchFilter,
+                         TaskOrder, TASK_ORDER_NAMES, TreeOrder, TREE_ORDER_NAMES, TreeNode,
+                         Series};
+use self::todo::period::{self, Period};
+use self::todo::recurrence::Recurrence;
+use self::todo::render::{self, Renderer};
+use self::todo::timesheet;
+use self::todo::tui;
 use self::todo::utils::read_editor_input;
 
 use chrono::*;
-use clap::{Arg, App, SubCommand};
+use clap::{Arg, App, AppSettings, ArgMatches, SubCommand};
+use serde::Serialize;
 
-fn split_title_body(text: &str) -> (&str, &str) {
-    // TODO Handle this failure
+fn split_title_body(text: &str) -> Result<(&str, &str)> {
     let result: Vec<&str> = text.split("\n==========\n").take(2).collect();
-    return (result[0], result[1]);
+    if result.len() < 2 {
+        return Err(Error::Validation("The ========== separator line is missing".to_owned()));
+    }
+    Ok((result[0], result[1]))
 }
 #[test]
 fn test_split_title_body() {
     let str = "Hello\n==========\nWorld\n==========\nSkip this part";
-    let (title, body) = split_title_body(&str);
+    let (title, body) = split_title_body(str).unwrap();
     assert_eq!(title, "Hello");
     assert_eq!(body, "World");
+    assert!(split_title_body("Hello\nWorld").is_err());
+}
+
+fn parse_int(text: &str, what: &str) -> Result<i32> {
+    i32::from_str(text)
+        .map_err(|_| Error::Validation(format!("{} should be a number, got {:?}", what, text)))
 }
 
-fn template_choice(default: &str) -> String {
-    let conn = connect_db();
-    let templates = Template::all(&conn);
+fn template_choice(store: &dyn Store, default: &str) -> Result<String> {
+    let templates = Template::all(store)?;
 
-    if templates.len() == 0 {
-        return default.to_owned();
+    if templates.is_empty() {
+        return Ok(default.to_owned());
     }
 
     println!("Enter the choice of your template:");
@@ -43,277 +65,883 @@ fn template_choice(default: &str) -> String {
     }
 
     let mut input = String::new();
-    io::stdin()
-        .read_line(&mut input)
-        .expect("Cannot read from stdin");
-    let index = usize::from_str(&input.trim()).expect("Cannot parse int");
-
-    // yay for panic
-    templates[index - 1].body.to_owned()
+    io::stdin().read_line(&mut input)?;
+    match usize::from_str(input.trim()) {
+        Ok(index) if index >= 1 && index <= templates.len() => {
+            Ok(templates[index - 1].body.to_owned())
+        }
+        _ => Err(Error::Validation(format!("Expected a template number, got {:?}", input.trim()))),
+    }
 }
 
-fn init() {
-    let conn = connect_db();
-    create_tables(&conn)
+fn migrate(store: &dyn Store, target: Option<i32>) -> Result<()> {
+    let steps = migrations::migrate(store, target)?;
+    if steps.is_empty() {
+        println!("Already at version {}", target.unwrap_or(migrations::latest_version(store)));
+    }
+    for (direction, migration) in steps {
+        let verb = match direction {
+            Direction::Up => "Applied",
+            Direction::Down => "Reverted",
+        };
+        println!("{} {:3}: {}", verb, migration.version, migration.name);
+    }
+    Ok(())
 }
 
-fn show_leaves() {
-    let conn = connect_db();
-    println!("id\tTitle");
-    for task in Task::open_leaves(&conn) {
-        println!("{}", task);
+fn migrate_status(store: &dyn Store) -> Result<()> {
+    for migration in migrations::status(store)? {
+        let state = if migration.applied { "applied" } else { "pending" };
+        println!("{:3}: [{}] {}", migration.version, state, migration.name);
     }
+    Ok(())
 }
 
-use std::collections::HashMap;
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Text,
+    Json,
+    Ndjson,
+    /// Only understood by `export-timesheet`
+    Csv,
+}
 
-fn tree(open: bool) {
-    let conn = connect_db();
+const FORMAT_NAMES: &[&str] = &["text", "json", "ndjson", "csv"];
 
-    // TODO this assumes tasks are sorted
-    let mut children_table = HashMap::new();
-    let mut task_table = HashMap::new();
-    let mut is_root_table = HashMap::new();
+/// The value of a global option, given either before or after the subcommand. Older clap
+/// versions only record it on the side it was given.
+fn global_value<'a>(app_matches: &'a ArgMatches, name: &str) -> Option<&'a str> {
+    app_matches.subcommand()
+        .1
+        .and_then(|matches| matches.value_of(name))
+        .or_else(|| app_matches.value_of(name))
+}
 
-    let tasks = Task::all(&conn);
+/// Whether a global flag is given, either before or after the subcommand
+fn global_flag(app_matches: &ArgMatches, name: &str) -> bool {
+    app_matches.is_present(name) ||
+    app_matches.subcommand().1.is_some_and(|matches| matches.is_present(name))
+}
 
-    for task in &tasks {
-        if let None = children_table.get(&task.id) {
-            task_table.insert(task.id, task.clone());
-            children_table.insert(task.id, Vec::new());
-            is_root_table.insert(task.id, true);
-        }
+fn parse_format(app_matches: &ArgMatches) -> Format {
+    match global_value(app_matches, "format") {
+        Some("json") => Format::Json,
+        Some("ndjson") => Format::Ndjson,
+        Some("csv") => Format::Csv,
+        _ => Format::Text,
     }
+}
+#[test]
+fn test_format_before_or_after_subcommand() {
+    let format = |args: &[&str]| parse_format(&app().get_matches_from(args));
+    assert_eq!(format(&["todo", "--format", "json", "leaves"]), Format::Json);
+    assert_eq!(format(&["todo", "leaves", "--format", "ndjson"]), Format::Ndjson);
+    let export = ["todo", "--format", "csv", "export-timesheet", "--from", "2017-03-01"];
+    assert_eq!(format(&export), Format::Csv);
+    assert_eq!(format(&["todo", "leaves"]), Format::Text);
+}
 
-    for task in &tasks {
-        if let Some(parent_id) = task.parent_id {
-            let id = task.id;
-            children_table
-                .get_mut(&parent_id)
-                .expect("Cannot unwrap table.get_mut()")
-                .push(id);
-            is_root_table.insert(id, false);
+fn to_json<T: Serialize + ?Sized>(value: &T) -> String {
+    serde_json::to_string(value).expect("Cannot serialize to JSON")
+}
+
+/// Prints `rows` as a single JSON array, or as one JSON object per line for `Format::Ndjson`.
+fn print_rows<T: Serialize>(rows: &[T], format: Format) {
+    if format == Format::Ndjson {
+        for row in rows {
+            println!("{}", to_json(row));
         }
+    } else {
+        println!("{}", to_json(rows));
     }
+}
 
-    struct State {
-        id: i32,
-        indent_level: i32,
+/// Quotes `field` if it contains a comma, a quote or a line break, as RFC 4180 asks.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace("\"", "\"\""))
+    } else {
+        field.to_owned()
     }
+}
+#[test]
+fn test_csv_field() {
+    assert_eq!(csv_field("plain"), "plain");
+    assert_eq!(csv_field("a, b"), "\"a, b\"");
+    assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+}
 
-    let mut queue = Vec::new();
-    for (id, _is_root) in &is_root_table {
-        if *_is_root {
-            queue.push(State {
-                           id: *id,
-                           indent_level: 0,
-                       })
-        }
-    }
+fn print_csv_row(fields: &[String]) {
+    let fields: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+    println!("{}", fields.join(","));
+}
+
+/// The `--tag` filter shared by the listing commands
+fn tag_filter<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("tag")
+        .long("tag")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1)
+        .help("Only tasks with this tag; repeat to require several tags")
+}
 
-    while queue.len() > 0 {
-        let top = queue.pop().unwrap();
+fn tag_filters(matches: &ArgMatches) -> Vec<String> {
+    matches.values_of("tag").map_or(vec![], |tags| tags.map(|tag| tag.to_owned()).collect())
+}
+
+/// `blocked` keeps only the tasks waiting on an open task (`Some(true)`) or only the others
+/// (`Some(false)`).
+fn show_leaves(store: &dyn Store,
+               order: TaskOrder,
+               tags: &[String],
+               blocked: Option<bool>,
+               format: Format,
+               renderer: &Renderer)
+               -> Result<()> {
+    let blockers = Task::open_blockers(store)?;
+    let mut leaves = Task::open_leaves(store, order)?;
+    leaves.retain(|task| {
+        task.has_tags(tags) && blocked.is_none_or(|b| b == blockers.contains_key(&task.id))
+    });
+    if format != Format::Text {
+        print_rows(&leaves, format);
+        return Ok(());
+    }
 
-        let t = task_table.get(&top.id).unwrap();
-        if !open || (*t).open {
-            for _ in 0..top.indent_level {
-                print!("    ")
+    let id_width = render::id_width(leaves.iter().map(|task| task.id)).max(2);
+    println!("{}", renderer.bold(&format!("{:>1$}  Title", "id", id_width)));
+    let mut ready = 0;
+    for task in &leaves {
+        match blockers.get(&task.id) {
+            Some(ids) => {
+                let ids: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+                println!("{}{}",
+                         renderer.task(task, id_width),
+                         renderer.dim(&format!(" (blocked by {})", ids.join(", "))));
             }
-            println!("{}", *t);
-
-            if let Some(children) = children_table.get(&top.id) {
-                for id in children {
-                    queue.push(State {
-                                   id: *id,
-                                   indent_level: top.indent_level + 1,
-                               });
-                }
+            None => {
+                ready += 1;
+                println!("{}", renderer.task(task, id_width));
             }
         }
     }
-
+    println!("\n{} ready, {} blocked", ready, leaves.len() - ready);
+    Ok(())
 }
 
-fn humanize_duration(seconds: f32) -> (i32, i32) {
-    let hours = (seconds / 3600f32).floor();
-    let minutes = ((seconds - hours * 3600f32) / 60f32).floor();
-    (hours as i32, minutes as i32)
+use std::collections::{HashMap, HashSet};
+
+/// What `tree` shows
+#[derive(Default)]
+struct TreeOptions {
+    /// Only this task and its subtree, instead of every root task
+    root: Option<i32>,
+    /// How many levels to show below the root tasks; 0 shows only the roots
+    depth: Option<i32>,
+    open_only: bool,
+    closed_only: bool,
+    /// Only tasks created or finished since then
+    since: Option<DateTime<Local>>,
+    /// Only tasks having all of these tags, or with a descendant that does
+    tags: Vec<String>,
+    /// Show, greyed out, the tasks hidden by the filters above whose descendants are shown
+    ancestors: bool,
+    time: bool,
+    order: TreeOrder,
 }
 
-fn dump_notes(notes: Vec<NoteAux>) -> String {
-    let mut result = "".to_string();
-    for note in notes {
-        let (hours, minutes) = humanize_duration(note.duration_seconds);
-        result.push_str(&format!("{}: {:02} hours {:02} minutes on {}-{}-{} \n{}\n",
-                                 note.id,
-                                 hours,
-                                 minutes,
-                                 note.date_start.day(),
-                                 note.date_start.month(),
-                                 note.date_start.year(),
-                                 note.body));
+impl TreeOptions {
+    fn matches(&self, task: &Task) -> bool {
+        let since = self.since.is_none_or(|since| {
+            task.date_created >= since || task.date_finished.is_some_and(|date| date >= since)
+        });
+        (!self.open_only || task.open) && (!self.closed_only || !task.open) && since
+    }
+
+    /// Whether an unmatched task is shown, greyed out, when a descendant is shown.
+    ///
+    /// Closed tasks and recent changes usually sit under older open tasks, so `--closed-only`
+    /// and `--since` show those ancestors anyway; `--open` still hides a closed subtree.
+    fn shows_ancestor(&self, task: &Task) -> bool {
+        let implied = self.closed_only || self.since.is_some();
+        self.ancestors || (implied && (!self.open_only || task.open))
     }
-    result
 }
 
-fn indent(text: String, level: i32) -> String {
-    let mut prefix = "".to_string();
-    for _ in 0..level {
-        prefix.push_str(" ")
+/// The indices of the `rows` to print, with whether each is greyed out, and the number of
+/// matching tasks left out because an ancestor is hidden.
+fn visible_rows(rows: &[TreeNode], options: &TreeOptions) -> (Vec<(usize, bool)>, usize) {
+    // walk the rows keeping the indices of the current row's ancestors, first to flag the tasks
+    // with a tagged descendant, then those with a matching one
+    let mut tagged = vec![false; rows.len()];
+    let mut ancestors: Vec<usize> = vec![];
+    for (i, node) in rows.iter().enumerate() {
+        let (task, depth) = (&node.task, node.depth as usize);
+        ancestors.truncate(depth);
+        if task.has_tags(&options.tags) {
+            tagged[i] = true;
+            for &ancestor in &ancestors {
+                tagged[ancestor] = true;
+            }
+        }
+        ancestors.push(i);
+    }
+    let mut matching_below = vec![false; rows.len()];
+    ancestors.clear();
+    for (i, node) in rows.iter().enumerate() {
+        let (task, depth) = (&node.task, node.depth as usize);
+        ancestors.truncate(depth);
+        if tagged[i] && options.matches(task) {
+            for &ancestor in &ancestors {
+                matching_below[ancestor] = true;
+            }
+        }
+        ancestors.push(i);
     }
 
-    let mut result = "".to_string();
-    let lines: Vec<&str> = text.split("\n").collect();
-    for i in 0..lines.len() {
-        let line = lines[i];
-        result.push_str(&format!("{}{}", prefix, line));
-        if i != lines.len() - 1 {
-            result.push_str("\n");
+    let mut shown = vec![false; rows.len()];
+    let mut hidden = 0;
+    let mut visible: Vec<(usize, bool)> = vec![];
+    ancestors.clear();
+    for (i, node) in rows.iter().enumerate() {
+        let (task, depth) = (&node.task, node.depth as usize);
+        ancestors.truncate(depth);
+        let matches = tagged[i] && options.matches(task);
+        let greyed = !matches && tagged[i] && matching_below[i] && options.shows_ancestor(task);
+        shown[i] = (matches || greyed) && ancestors.iter().all(|&ancestor| shown[ancestor]);
+        ancestors.push(i);
+        if !shown[i] {
+            if matches {
+                hidden += 1;
+            }
+            continue;
         }
+        visible.push((i, greyed));
     }
-    result
+    (visible, hidden)
 }
 #[test]
-fn test_indent() {
-    let str = "def my_func():
-    print 'xd'
-    if 1 < 2:
-        print 'haha'";
-    let expected = "    def my_func():
-        print 'xd'
-        if 1 < 2:
-            print 'haha'";
-    let result = indent(str.to_string(), 4);
-    println!("\nresult:\n{}", result);
-    println!("\nexpected:\n{}", expected);
-    assert_eq!(result, expected);
-}
-
-fn dump_reviews(reviews: Vec<Review>) -> String {
-    let mut result = "".to_string();
+fn test_visible_rows() {
+    let store = MemoryStore::new();
+    new_task(&store, None, Some("Root"), Some(""), None, None, None).unwrap();
+    new_task(&store, Some(1), Some("Done"), Some(""), None, None, None).unwrap();
+    new_task(&store, Some(1), Some("Todo"), Some(""), None, None, None).unwrap();
+    let since = Local::now();
+    Task::finish(&store, 2).unwrap();
+    let rows = Task::sorted_subtree(&store, None, None, TreeOrder::Id).unwrap();
+    let ids = |options: &TreeOptions| {
+        let (visible, hidden) = visible_rows(&rows, options);
+        let ids: Vec<(i32, bool)> =
+            visible.iter().map(|&(i, greyed)| (rows[i].task.id, greyed)).collect();
+        (ids, hidden)
+    };
+
+    let closed = TreeOptions { closed_only: true, ..TreeOptions::default() };
+    assert_eq!(ids(&closed), (vec![(1, true), (2, false)], 0));
+    let since = TreeOptions { since: Some(since), ..TreeOptions::default() };
+    assert_eq!(ids(&since), (vec![(1, true), (2, false)], 0));
+    let open = TreeOptions { open_only: true, ..TreeOptions::default() };
+    assert_eq!(ids(&open), (vec![(1, false), (3, false)], 0));
+    let tagged = TreeOptions { tags: vec!["none".to_owned()], ..TreeOptions::default() };
+    assert_eq!(ids(&tagged), (vec![], 0));
+}
+
+/// In JSON formats the tasks are listed as `TaskAux` rows in the order the tree would print them.
+///
+/// A task hidden by the filters hides its subtree too, unless `options.ancestors` is set or the
+/// filter is `--closed-only` or `--since`.
+fn tree(store: &dyn Store,
+        options: &TreeOptions,
+        format: Format,
+        renderer: &Renderer)
+        -> Result<()> {
+    let rows = Task::sorted_subtree(store, options.root, options.depth, options.order)?;
+    let mut aux_table: HashMap<i32, TaskAux> = HashMap::new();
+    if options.time || format != Format::Text {
+        for task in Task::all_aux(store)? {
+            aux_table.insert(task.id, task);
+        }
+    }
+    let (visible, hidden) = visible_rows(&rows, options);
+
+    if format != Format::Text {
+        let aux_rows: Vec<TaskAux> = visible.iter()
+            .map(|&(i, _)| aux_table[&rows[i].task.id].clone())
+            .collect();
+        print_rows(&aux_rows, format);
+        return Ok(());
+    }
+    let depths: Vec<usize> = visible.iter().map(|&(i, _)| rows[i].depth as usize).collect();
+    let id_width = render::id_width(visible.iter().map(|&(i, _)| rows[i].task.id));
+    for (&(i, greyed), guide) in visible.iter().zip(render::tree_guides(&depths)) {
+        let task = &rows[i].task;
+        if options.time {
+            print!("{}  ", render::duration(aux_table[&task.id].total_duration_seconds));
+        }
+        let label = if greyed {
+            renderer.dim(&Renderer::plain(renderer.width).task_label(task))
+        } else {
+            renderer.task_label(task)
+        };
+        println!("{}  {}{}",
+                 renderer.dim(&format!("{:>1$}", task.id, id_width)),
+                 renderer.dim(&guide),
+                 label);
+    }
+    if hidden > 0 {
+        println!("\n{} more under hidden tasks; use --ancestors to show them", hidden);
+    }
+    Ok(())
+}
+
+fn humanize_duration(seconds: f32) -> (i32, i32) {
+    let hours = (seconds / 3600f32).floor();
+    let minutes = ((seconds - hours * 3600f32) / 60f32).floor();
+    (hours as i32, minutes as i32)
+}
+
+/// Each note's ID, duration and day, then its body wrapped below, indented by `indent` spaces
+/// and separated by blank lines.
+fn dump_notes(notes: &[NoteAux], indent: usize, renderer: &Renderer) -> String {
+    let id_width = render::id_width(notes.iter().map(|note| note.id));
+    let blocks: Vec<String> = notes.iter()
+        .map(|note| {
+            format!("{}{}  {}  on {}-{}-{}\n{}",
+                    " ".repeat(indent),
+                    renderer.dim(&format!("{:>1$}", note.id, id_width)),
+                    render::duration(note.duration_seconds),
+                    note.date_start.day(),
+                    note.date_start.month(),
+                    note.date_start.year(),
+                    renderer.wrap(&note.body, indent + id_width + 2))
+        })
+        .collect();
+    blocks.join("\n\n")
+}
+
+/// Each task's ID, status and title, then its notes indented below. Notes and tasks are
+/// separated by blank lines.
+fn dump_reviews(reviews: Vec<Review>, renderer: &Renderer) -> String {
+    let id_width = render::id_width(reviews.iter().map(|review| review.task_id));
+    let note_width = render::id_width(reviews.iter().filter_map(|review| review.note_id));
+    let indent = " ".repeat(id_width + 2);
+    let mut blocks: Vec<String> = vec![];
     // not gonna make a hash table for this; assumes the vector is sorted by task_id
     let mut prev = -1;
+    let mut after_header = false;
     for review in reviews {
         if review.task_id != prev {
-            let status = if review.open { "OPEN" } else { "DONE" };
+            let finished = match review.date_finished {
+                Some(date) if !review.open => {
+                    format!(" (finished on {}-{}-{})", date.day(), date.month(), date.year())
+                }
+                _ => String::new(),
+            };
             prev = review.task_id;
-            result.push_str(&format!("\n{}: [{}] {}\n", review.task_id, status, review.task_title));
+            blocks.push(format!("{}  {}  {}{}",
+                                renderer.dim(&format!("{:>1$}", review.task_id, id_width)),
+                                renderer.status(review.open),
+                                renderer.bold(&review.task_title),
+                                renderer.dim(&finished)));
+            after_header = true;
+        }
+        if let (Some(note_id), Some(note_body)) = (review.note_id, review.note_body) {
+            let note = format!("{}{}  on {}-{}-{}\n{}",
+                               indent,
+                               renderer.dim(&format!("{:>1$}", note_id, note_width)),
+                               review.last_updated.day(),
+                               review.last_updated.month(),
+                               review.last_updated.year(),
+                               renderer.wrap(&note_body, indent.len() + note_width + 2));
+            match blocks.last_mut() {
+                Some(header) if after_header => {
+                    header.push('\n');
+                    header.push_str(&note);
+                }
+                _ => blocks.push(note),
+            }
+            after_header = false;
         }
-        result.push_str(&indent(format!("\n{}: on {}-{}-{}\n{}\n",
-                                        review.note_id,
-                                        review.last_updated.day(),
-                                        review.last_updated.month(),
-                                        review.last_updated.year(),
-                                        &indent(review.note_body, 4)),
-                                4))
     }
-    result
+    blocks.join("\n\n")
 }
 
-fn view_task(task_id: i32) {
-    let conn = connect_db();
-    let task = Task::find_aux(&conn, task_id).expect("Task ID does not exist");
+/// What `view-task` prints in JSON formats
+#[derive(Serialize)]
+struct TaskView<'a> {
+    task: &'a TaskAux,
+    notes: &'a [NoteAux],
+}
+
+fn view_task(store: &dyn Store, task_id: i32, format: Format, renderer: &Renderer) -> Result<()> {
+    let task = Task::find_aux(store, task_id)?;
+    let notes = Task::find_notes_aux(store, task_id)?;
+    if format != Format::Text {
+        println!("{}",
+                 to_json(&TaskView {
+                             task: &task,
+                             notes: &notes,
+                         }));
+        return Ok(());
+    }
+
     let (hours, minutes) = humanize_duration(task.duration_seconds);
-    println!("[{}] (accumulated: {:02} hours {:02} minutes)\n{}\n\n[Notes]",
-             task.title,
+    let (total_hours, total_minutes) = humanize_duration(task.total_duration_seconds);
+    let mut finished = match task.date_finished {
+        Some(date) => format!(" (finished on {}-{}-{})", date.day(), date.month(), date.year()),
+        None => String::new(),
+    };
+    if let Some(series_id) = task.series_id {
+        finished.push_str(&format!(" (repeats {})", Series::find(store, series_id)?.recurrence));
+    }
+    println!("{} {}{} (own: {:02} hours {:02} minutes, total: {:02} hours {:02} minutes)\n{}\n\n\
+              {}",
+             renderer.status(task.open),
+             renderer.bold(&format!("[{}]", task.title)),
+             finished,
              hours,
              minutes,
-             task.body);
+             total_hours,
+             total_minutes,
+             renderer.wrap(&task.body, 0),
+             renderer.bold("[Notes]"));
+
+    println!("{}", dump_notes(&notes, 0, renderer));
+    Ok(())
+}
+
+fn report(store: &dyn Store,
+          period: &Period,
+          tags: &[String],
+          format: Format,
+          renderer: &Renderer)
+          -> Result<()> {
+    let mut reports = Task::report(store, period.start()?, period.end()?)?;
+    reports.retain(|report| report.task.has_tags(tags));
+    if format != Format::Text {
+        print_rows(&reports, format);
+        return Ok(());
+    }
 
-    let notes = Task::find_notes_aux(&conn, task_id);
+    let last_day = period.to.pred();
+    println!("Report from {}-{}-{} to {}-{}-{}",
+             period.from.day(),
+             period.from.month(),
+             period.from.year(),
+             last_day.day(),
+             last_day.month(),
+             last_day.year());
 
-    println!("{}", dump_notes(notes))
+    // durations first, so they line up in a column with the total
+    let id_width = render::id_width(reports.iter().map(|report| report.task.id));
+    let indent = render::duration(0f32).len() + 2;
+    let mut total_seconds = 0f32;
+    for report in reports {
+        println!("\n{}  {}",
+                 render::duration(report.duration_seconds),
+                 renderer.task(&report.task, id_width));
+        println!("{}", dump_notes(&report.notes, indent, renderer));
+        total_seconds += report.duration_seconds;
+    }
+
+    println!("\n{}  {}", render::duration(total_seconds), renderer.bold("Total"));
+    Ok(())
 }
 
-fn new_task(parent_id: Option<i32>,
+fn new_task(store: &dyn Store,
+            parent_id: Option<i32>,
             title: Option<&str>,
             body: Option<&str>,
-            template: Option<&str>) {
+            template: Option<&str>,
+            priority: Option<i32>,
+            due_date: Option<NaiveDate>)
+            -> Result<Task> {
     let task_body = if let Some(name) = template {
-        let conn = connect_db();
-        Template::existing(&conn, name).unwrap()
+        Template::find(store, name)?
     } else {
-        template_choice("Description for your task")
+        template_choice(store, "Description for your task")?
     };
     let editor_title = title.unwrap_or("Title for your task");
     let editor_body = body.unwrap_or(&task_body);
     let launch_editor = !(title.is_some() && body.is_some());
     let date_created = Local::now();
-    if launch_editor {
+    let mut task = if launch_editor {
         let template = format!("{}\n==========\n{}", editor_title, editor_body);
-        let input = read_editor_input(&template).expect("Failed to get user input");
-        let (input_title, input_body) = split_title_body(&input);
-        let conn = connect_db();
-        Task::new(parent_id, input_title, input_body, date_created).create(&conn);
+        let input = read_editor_input(&template)?;
+        let (input_title, input_body) = split_title_body(&input)?;
+        Task::new(parent_id, input_title, input_body, date_created)
     } else {
-        let conn = connect_db();
-        Task::new(parent_id, editor_title, editor_body, date_created).create(&conn);
+        Task::new(parent_id, editor_title, editor_body, date_created)
     };
+    task.priority = priority;
+    task.due_date = due_date;
+    task.create(store)
 }
 
-fn new_note(parent_id: i32, template: Option<&str>) {
-    let conn = connect_db();
-    let task = Task::find(&conn, parent_id).expect("Task ID does not exist");
-    let notes = Task::find_notes_aux(&conn, task.id);
+#[test]
+fn test_new_task_without_editor() {
+    let store = MemoryStore::new();
+    new_task(&store, None, Some("Parent"), Some("p"), None, None, None).unwrap();
+    new_task(&store, Some(1), Some("Child"), Some("c"), None, Some(2), None).unwrap();
+
+    let child = Task::find(&store, 2).unwrap();
+    assert_eq!((child.parent_id, child.priority), (Some(1), Some(2)));
+    assert_eq!((child.title.as_str(), child.body.as_str()), ("Child", "c"));
+    assert_eq!(Task::open_leaves(&store, TaskOrder::Created).unwrap().len(), 1);
+    assert!(new_task(&store, Some(3), Some("Orphan"), Some(""), None, None, None).is_err());
+    assert!(new_task(&store, None, Some("Urgent"), Some(""), None, Some(0), None).is_err());
+    assert_eq!(new_task(&store, Some(2), Some("Grandchild"), Some(""), None, None, None)
+                   .unwrap()
+                   .id,
+               3);
+}
+
+/// The note's body, then the task's body, then the task's notes for reference.
+fn note_editor_template(store: &dyn Store, task: &Task, template: Option<&str>) -> Result<String> {
+    let notes = Task::find_notes_aux(store, task.id)?;
 
     let note_body = if let Some(name) = template {
-        let conn = connect_db();
-        Template::existing(&conn, name).unwrap()
+        Template::find(store, name)?
     } else {
         String::from("Add your note here")
     };
 
-    let template = format!("{}\n==========\n{}\n==========\n{}",
-                           &note_body,
-                           task.body,
-                           dump_notes(notes));
+    Ok(format!("{}\n==========\n{}\n==========\n{}",
+               &note_body,
+               task.body,
+               dump_notes(&notes, 0, &Renderer::plain(render::DEFAULT_WIDTH))))
+}
+
+/// Reads `new-note`'s `--date`, `--start`, `--end` and `--duration`, if any were given.
+fn note_times(matches: &ArgMatches) -> Result<Option<(DateTime<Local>, DateTime<Local>)>> {
+    let (start, end, duration) = (matches.value_of("start"),
+                                  matches.value_of("end"),
+                                  matches.value_of("duration"));
+    if start.is_none() && end.is_none() && duration.is_none() {
+        if matches.is_present("date") {
+            return Err(Error::Validation("--date needs --start, --end or --duration".to_owned()));
+        }
+        return Ok(None);
+    }
+
+    let now = Local::now();
+    let day = match matches.value_of("date") {
+        Some(text) => period::parse_day(text, now.date())?,
+        None => now.date(),
+    };
+    if day != now.date() && start.is_none() && end.is_none() {
+        return Err(Error::Validation("A note on another day needs --start or --end".to_owned()));
+    }
+    let start = match start {
+        Some(text) => Some(period::parse_time(text, day)?),
+        None => None,
+    };
+    let end = match end {
+        Some(text) => Some(period::parse_time(text, day)?),
+        None => None,
+    };
+    let duration = match duration {
+        Some(text) => Some(period::parse_duration(text)?),
+        None => None,
+    };
+    period::time_range(start, end, duration, now).map(Some)
+}
+
+/// Without `times`, the note lasts as long as the editor stays open.
+fn new_note(store: &dyn Store,
+            parent_id: i32,
+            template: Option<&str>,
+            times: Option<(DateTime<Local>, DateTime<Local>)>)
+            -> Result<Note> {
+    let task = Task::find(store, parent_id)?;
+
+    let template = note_editor_template(store, &task, template)?;
     let date_start = Local::now();
-    let input = read_editor_input(&template).expect("Failed to get user input");
-    let date_end = Local::now();
-    let (note_body, new_task_body) = split_title_body(&input);
+    let input = read_editor_input(&template)?;
+    let (date_start, date_end) = times.unwrap_or((date_start, Local::now()));
+    let (note_body, new_task_body) = split_title_body(&input)?;
 
-    let conn = connect_db();
-    Note::create(&conn,
+    Note::create(store,
                  parent_id,
                  note_body,
                  new_task_body,
                  date_start,
-                 date_end);
-    ()
+                 date_end)
 }
 
-fn finish(task_id: i32) {
-    let conn = connect_db();
-    Task::finish(&conn, task_id);
+fn start(store: &dyn Store, task_id: i32) -> Result<()> {
+    Timer::start(store, task_id)?;
+    Ok(())
 }
 
-fn new_template(name: &str) {
-    let existing = {
-        let conn = connect_db();
-        Template::existing(&conn, name)
-    };
+/// Without `note`, asks for the note in `$EDITOR`; the time spent there is not counted.
+fn stop(store: &dyn Store, note: Option<&str>, template: Option<&str>) -> Result<Note> {
+    let date_end = Local::now();
+    let timer = Timer::active(store)?
+        .ok_or_else(|| Error::Validation("No timer is running".to_owned()))?;
+    let task = Task::find(store, timer.task_id)?;
+
+    if let Some(body) = note {
+        return Timer::stop(store, body, &task.body, date_end);
+    }
+    let template = note_editor_template(store, &task, template)?;
+    let input = read_editor_input(&template)?;
+    let (note_body, task_body) = split_title_body(&input)?;
+    Timer::stop(store, note_body, task_body, date_end)
+}
+
+fn status(store: &dyn Store, format: Format) -> Result<()> {
+    let timer = Timer::active(store)?;
+    if format != Format::Text {
+        println!("{}", to_json(&timer));
+        return Ok(());
+    }
+
+    match timer {
+        Some(timer) => {
+            let task = Task::find(store, timer.task_id)?;
+            let seconds = (Local::now() - timer.date_start).num_seconds() as f32;
+            let (hours, minutes) = humanize_duration(seconds);
+            println!("{} (running for {:02} hours {:02} minutes, since {})",
+                     task,
+                     hours,
+                     minutes,
+                     timer.date_start.format("%H:%M"));
+        }
+        None => println!("No timer is running"),
+    }
+    Ok(())
+}
+
+fn edit_task(store: &dyn Store, task_id: i32) -> Result<()> {
+    let task = Task::find(store, task_id)?;
+
+    let template = format!("{}\n==========\n{}", task.title, task.body);
+    let input = read_editor_input(&template)?;
+    let (title, body) = split_title_body(&input)?;
+
+    Task::update(store, task_id, title, body)
+}
+
+fn edit_note(store: &dyn Store, note_id: i32) -> Result<()> {
+    let note = Note::find(store, note_id)?;
+    let task = Task::find(store, note.task_id)?;
+
+    let template = format!("{}\n==========\n{}", note.body, task.body);
+    let input = read_editor_input(&template)?;
+    let (note_body, task_body) = split_title_body(&input)?;
+
+    Note::update(store, note_id, note_body, task_body)
+}
+
+/// Prints a newly created row's ID, or the whole row as JSON, for use in shell pipelines.
+fn print_created<T: Serialize>(row: &T, id: i32, json: bool) {
+    if json {
+        println!("{}", to_json(row));
+    } else {
+        println!("{}", id);
+    }
+}
+
+fn finish(store: &dyn Store, task_id: i32) -> Result<()> {
+    if let Some(next) = Task::finish(store, task_id)? {
+        match next.due_date {
+            Some(due_date) => {
+                println!("Task {} comes back as task {}, due {}", task_id, next.id, due_date)
+            }
+            None => println!("Task {} comes back as task {}", task_id, next.id),
+        }
+    }
+    Ok(())
+}
+
+fn repeat(store: &dyn Store, task_id: i32, recurrence: Option<&str>) -> Result<()> {
+    match recurrence {
+        Some(recurrence) => Task::repeat(store, task_id, recurrence).map(|_| ()),
+        None => Task::stop_repeating(store, task_id),
+    }
+}
+
+fn move_task(store: &dyn Store, task_id: i32, parent_id: Option<i32>) -> Result<()> {
+    Task::set_parent(store, task_id, parent_id)
+}
+
+fn tag(store: &dyn Store, task_id: i32, changes: &[&str]) -> Result<()> {
+    Task::tag(store, task_id, changes)
+}
+
+fn block(store: &dyn Store, task_id: i32, blocker_id: i32, remove: bool) -> Result<()> {
+    if remove {
+        Task::unblock(store, task_id, blocker_id)
+    } else {
+        Task::block(store, task_id, blocker_id)
+    }
+}
+
+fn reopen(store: &dyn Store, task_id: i32) -> Result<()> {
+    Task::reopen(store, task_id)
+}
+
+fn new_template(store: &dyn Store, name: &str) -> Result<()> {
+    let existing = Template::existing(store, name)?;
 
     let template = existing.unwrap_or(String::from("Type your template body here"));
 
-    let body = read_editor_input(&template).expect("Failed to get user input");
-    let conn = connect_db();
-    Template::upsert(&conn, name, &body);
-    ()
+    let body = read_editor_input(&template)?;
+    Template::upsert(store, name, &body)
 }
 
-fn review(days: i32) {
-    let conn = connect_db();
-    let reviews = Task::find_recently_updated(&conn, days);
+/// Prints CSV unless a JSON format was asked for.
+fn export_timesheet(store: &dyn Store,
+                    period: &Period,
+                    per_day: bool,
+                    format: Format)
+                    -> Result<()> {
+    let entries = timesheet::entries(store, period.start()?, period.end()?)?;
+    let json = format == Format::Json || format == Format::Ndjson;
 
-    println!("{}", dump_reviews(reviews))
+    if per_day {
+        let days = timesheet::per_day(&entries);
+        if json {
+            print_rows(&days, format);
+            return Ok(());
+        }
+        print_csv_row(&["task_id".to_owned(),
+                        "task_path".to_owned(),
+                        "date".to_owned(),
+                        "duration_hours".to_owned()]);
+        for day in days {
+            print_csv_row(&[day.task_id.to_string(),
+                            day.task_path,
+                            day.date,
+                            format!("{:.2}", day.duration_hours)]);
+        }
+    } else {
+        if json {
+            print_rows(&entries, format);
+            return Ok(());
+        }
+        print_csv_row(&["task_id".to_owned(),
+                        "task_path".to_owned(),
+                        "note_id".to_owned(),
+                        "start".to_owned(),
+                        "end".to_owned(),
+                        "duration_hours".to_owned()]);
+        for entry in entries {
+            print_csv_row(&[entry.task_id.to_string(),
+                            entry.task_path,
+                            entry.note_id.to_string(),
+                            entry.date_start.to_rfc3339(),
+                            entry.date_end.to_rfc3339(),
+                            format!("{:.2}", entry.duration_hours)]);
+        }
+    }
+    Ok(())
 }
 
-fn main() {
-    let app = App::new("Todo list")
+fn search(store: &dyn Store,
+          query: &str,
+          filter: &SearchFilter,
+          format: Format,
+          renderer: &Renderer)
+          -> Result<()> {
+    let results = Task::search(store, query, filter)?;
+    if format != Format::Text {
+        print_rows(&results, format);
+        return Ok(());
+    }
+
+    let id_width = render::id_width(results.iter().map(|result| result.task_id));
+    for result in results {
+        let source = match result.note_id {
+            Some(note_id) => format!("note {}", note_id),
+            None => "task".to_owned(),
+        };
+        let found = format!(" ({} on {}-{}-{})",
+                            source,
+                            result.date.day(),
+                            result.date.month(),
+                            result.date.year());
+        println!("{}  {}  {}{}\n{}\n",
+                 renderer.dim(&format!("{:>1$}", result.task_id, id_width)),
+                 renderer.status(result.open),
+                 renderer.bold(&result.task_title),
+                 renderer.dim(&found),
+                 renderer.wrap(&result.snippet, id_width + 2));
+    }
+    Ok(())
+}
+
+fn agenda(store: &dyn Store, format: Format, renderer: &Renderer) -> Result<()> {
+    let entries = agenda::agenda(store, Local::today())?;
+    if format != Format::Text {
+        print_rows(&entries, format);
+        return Ok(());
+    }
+
+    let id_width = render::id_width(entries.iter().map(|entry| entry.task.id));
+    let mut group = None;
+    for entry in entries {
+        if group != Some(entry.group) {
+            group = Some(entry.group);
+            println!("\n{}", renderer.bold(&format!("[{}]", entry.group.name())));
+        }
+        println!("{}", renderer.task(&entry.task, id_width));
+    }
+    Ok(())
+}
+
+fn review(store: &dyn Store,
+          days: i32,
+          tags: &[String],
+          format: Format,
+          renderer: &Renderer)
+          -> Result<()> {
+    let mut reviews = Task::find_recently_updated(store, days)?;
+    if !tags.is_empty() {
+        let tagged: HashSet<i32> = Task::all(store)?
+            .iter()
+            .filter(|task| task.has_tags(tags))
+            .map(|task| task.id)
+            .collect();
+        reviews.retain(|review| tagged.contains(&review.task_id));
+    }
+    if format != Format::Text {
+        print_rows(&reviews, format);
+        return Ok(());
+    }
+
+    println!("{}", dump_reviews(reviews, renderer));
+    Ok(())
+}
+
+fn app() -> App<'static, 'static> {
+    App::new("Todo list")
         .version("0.0")
         .author("Ngo The Trung <ngo.the.trung.aczne@gmail.com>")
         .about("My todo list")
-        .subcommand(SubCommand::with_name("init").about("Initialize the tables"))
+        .arg(Arg::with_name("dry-run")
+            .long("dry-run")
+            .global(true)
+            .help("Run against an in-memory copy of the database and print what would change"))
+        .arg(Arg::with_name("format")
+            .long("format")
+            .global(true)
+            .takes_value(true)
+            .possible_values(FORMAT_NAMES)
+            .help("Print JSON (an array, or one object per line for ndjson) instead of text; \
+                   export-timesheet also prints CSV"))
+        .subcommand(SubCommand::with_name("migrate")
+            .about("Bring the database schema up to date")
+            .arg(Arg::with_name("to")
+                .long("to")
+                .takes_value(true)
+                .help("Migrate up or down to this schema version instead of the latest; 0 drops \
+                       every table"))
+            .arg(Arg::with_name("status")
+                .long("status")
+                .help("List the migrations and whether they have been applied")))
         .subcommand(SubCommand::with_name("new-task")
             .usage("Adds a new task entry, outputting its ID")
             .arg(Arg::with_name("parent")
@@ -332,10 +960,63 @@ fn main() {
                 .short("t")
                 .long("template")
                 .takes_value(true)
-                .help("A template for this note's body")))
+                .help("A template for this note's body"))
+            .arg(Arg::with_name("priority")
+                .short("p")
+                .long("priority")
+                .takes_value(true)
+                .help("From 1 (highest) to 5 (lowest)"))
+            .arg(Arg::with_name("due")
+                .long("due")
+                .takes_value(true)
+                .help("Due date: today, tomorrow or YYYY-MM-DD"))
+            .arg(Arg::with_name("repeat")
+                .long("repeat")
+                .takes_value(true)
+                .help("Create the next instance whenever this one is finished; see repeat"))
+            .arg(Arg::with_name("json")
+                .long("json")
+                .help("Print the created task as JSON instead of just its ID; same as --format \
+                       json")))
         .subcommand(SubCommand::with_name("tree")
             .about("List down all tasks in a tree format")
-            .arg(Arg::with_name("open").short("o")))
+            .arg(Arg::with_name("root")
+                .index(1)
+                .takes_value(true)
+                .help("Only show this task and its subtasks"))
+            .arg(Arg::with_name("depth")
+                .long("depth")
+                .takes_value(true)
+                .help("How many levels of subtasks to show; 0 shows only the root tasks"))
+            .arg(Arg::with_name("open")
+                .short("o")
+                .long("open")
+                .conflicts_with("closed-only")
+                .help("Only show open tasks"))
+            .arg(Arg::with_name("closed-only")
+                .long("closed-only")
+                .help("Only show finished tasks"))
+            .arg(Arg::with_name("since")
+                .long("since")
+                .takes_value(true)
+                .help("Only show tasks created or finished since this day: today, yesterday or \
+                       YYYY-MM-DD"))
+            .arg(Arg::with_name("ancestors")
+                .long("ancestors")
+                .help("Show, greyed out, the tasks left out by the filters that have subtasks \
+                       which are not; implied by --closed-only and --since"))
+            .arg(Arg::with_name("time")
+                .short("t")
+                .long("time")
+                .help("Show the time spent in each task and its subtasks"))
+            .arg(Arg::with_name("sort")
+                .long("sort")
+                .takes_value(true)
+                .possible_values(TREE_ORDER_NAMES)
+                .default_value("id")
+                .help("How to sort the tasks at each level: oldest first, newest first, by \
+                       title, highest priority first, or most recent activity first"))
+            .arg(tag_filter()))
         .subcommand(SubCommand::with_name("view-task")
             .help("View a task's contents & metadata")
             .arg(Arg::with_name("task")
@@ -343,7 +1024,24 @@ fn main() {
                 .required(true)
                 .takes_value(true)
                 .help("Task's ID")))
-        .subcommand(SubCommand::with_name("leaves").about("List down the leaf tasks & their id"))
+        .subcommand(SubCommand::with_name("leaves")
+            .about("List down the leaf tasks & their id")
+            .arg(Arg::with_name("sort")
+                .long("sort")
+                .takes_value(true)
+                .possible_values(TASK_ORDER_NAMES)
+                .default_value("created")
+                .help("Newest first, earliest due date first, or highest priority first"))
+            .arg(Arg::with_name("ready")
+                .long("ready")
+                .conflicts_with("blocked")
+                .help("Hide the tasks still waiting on an open task"))
+            .arg(Arg::with_name("blocked")
+                .long("blocked")
+                .help("Only show the tasks still waiting on an open task"))
+            .arg(tag_filter()))
+        .subcommand(SubCommand::with_name("agenda")
+            .about("List open tasks with a due date: overdue, today, this week and later"))
         .subcommand(SubCommand::with_name("new-note")
             .help("Update a task with a new note")
             .arg(Arg::with_name("task")
@@ -355,7 +1053,66 @@ fn main() {
                 .short("t")
                 .long("template")
                 .takes_value(true)
-                .help("A template for this note's body")))
+                .help("A template for this note's body"))
+            .arg(Arg::with_name("start")
+                .long("start")
+                .takes_value(true)
+                .help("When the work started, as HH:MM"))
+            .arg(Arg::with_name("end")
+                .long("end")
+                .takes_value(true)
+                .help("When the work ended, as HH:MM"))
+            .arg(Arg::with_name("duration")
+                .long("duration")
+                .takes_value(true)
+                .help("How long the work took, such as 1h30m"))
+            .arg(Arg::with_name("date")
+                .long("date")
+                .takes_value(true)
+                .help("The day of --start and --end: today, yesterday or YYYY-MM-DD"))
+            .arg(Arg::with_name("json")
+                .long("json")
+                .help("Print the created note as JSON instead of just its ID; same as --format \
+                       json")))
+        .subcommand(SubCommand::with_name("start")
+            .about("Start timing work on a task")
+            .arg(Arg::with_name("task")
+                .index(1)
+                .required(true)
+                .takes_value(true)
+                .help("Task's ID")))
+        .subcommand(SubCommand::with_name("stop")
+            .about("Stop the running timer and record it as a note, outputting its ID")
+            .arg(Arg::with_name("note")
+                .long("note")
+                .takes_value(true)
+                .help("The note's body; opens $EDITOR if missing"))
+            .arg(Arg::with_name("template")
+                .short("t")
+                .long("template")
+                .takes_value(true)
+                .conflicts_with("note")
+                .help("A template for this note's body"))
+            .arg(Arg::with_name("json")
+                .long("json")
+                .help("Print the created note as JSON instead of just its ID; same as --format \
+                       json")))
+        .subcommand(SubCommand::with_name("status")
+            .about("Show the running timer"))
+        .subcommand(SubCommand::with_name("edit-task")
+            .about("Edit a task's title and description")
+            .arg(Arg::with_name("task")
+                .index(1)
+                .required(true)
+                .takes_value(true)
+                .help("Task's ID")))
+        .subcommand(SubCommand::with_name("edit-note")
+            .about("Edit a note, along with its task's description")
+            .arg(Arg::with_name("note")
+                .index(1)
+                .required(true)
+                .takes_value(true)
+                .help("Note's ID")))
         .subcommand(SubCommand::with_name("finish")
             .help("Mark a task as done")
             .arg(Arg::with_name("task")
@@ -363,6 +1120,76 @@ fn main() {
                 .required(true)
                 .takes_value(true)
                 .help("Task's ID")))
+        .subcommand(SubCommand::with_name("move")
+            .about("Move a task and its subtasks under another task")
+            .arg(Arg::with_name("task")
+                .index(1)
+                .required(true)
+                .takes_value(true)
+                .help("Task's ID"))
+            .arg(Arg::with_name("parent")
+                .index(2)
+                .required_unless("root")
+                .takes_value(true)
+                .help("New parent task's ID"))
+            .arg(Arg::with_name("root")
+                .long("root")
+                .conflicts_with("parent")
+                .help("Make the task a root task")))
+        .subcommand(SubCommand::with_name("block")
+            .about("Mark a task as waiting on another one to be finished")
+            .arg(Arg::with_name("task")
+                .index(1)
+                .required(true)
+                .takes_value(true)
+                .help("Task's ID"))
+            .arg(Arg::with_name("on")
+                .long("on")
+                .required(true)
+                .takes_value(true)
+                .help("ID of the task it waits on"))
+            .arg(Arg::with_name("remove")
+                .long("remove")
+                .help("Stop waiting on that task instead")))
+        .subcommand(SubCommand::with_name("repeat")
+            .about("Make a task come back when it is finished")
+            .after_help("A recurrence is daily, weekly, monthly, yearly, every N days, weeks, \
+                         months or years, or the day fields of a cron expression: <day of month> \
+                         <month> <day of week>, such as \"* * mon-fri\" or \"1,15 * *\".")
+            .arg(Arg::with_name("task")
+                .index(1)
+                .required(true)
+                .takes_value(true)
+                .help("Task's ID"))
+            .arg(Arg::with_name("recurrence")
+                .index(2)
+                .required_unless("stop")
+                .multiple(true)
+                .help("How often the task comes back"))
+            .arg(Arg::with_name("stop")
+                .long("stop")
+                .conflicts_with("recurrence")
+                .help("Stop the task from coming back")))
+        .subcommand(SubCommand::with_name("tag")
+            .about("Add (+tag) or remove (-tag) tags on a task")
+            .setting(AppSettings::AllowLeadingHyphen)
+            .arg(Arg::with_name("task")
+                .index(1)
+                .required(true)
+                .takes_value(true)
+                .help("Task's ID"))
+            .arg(Arg::with_name("changes")
+                .index(2)
+                .required(true)
+                .multiple(true)
+                .help("Tags to add, such as +work, or remove, such as -urgent")))
+        .subcommand(SubCommand::with_name("reopen")
+            .help("Mark a finished task as open again")
+            .arg(Arg::with_name("task")
+                .index(1)
+                .required(true)
+                .takes_value(true)
+                .help("Task's ID")))
         .subcommand(SubCommand::with_name("new-template")
             .help("Create a new template")
             .arg(Arg::with_name("name")
@@ -370,66 +1197,303 @@ fn main() {
                 .required(true)
                 .takes_value(true)
                 .help("Template's name (unique)")))
+        .subcommand(SubCommand::with_name("report")
+            .about("Summarize the time spent on each task over a period")
+            .arg(Arg::with_name("period")
+                .index(1)
+                .required_unless("from")
+                .conflicts_with("from")
+                .possible_values(todo::period::PERIOD_NAMES)
+                .help("A named period, such as yesterday or last-week"))
+            .arg(Arg::with_name("from")
+                .long("from")
+                .takes_value(true)
+                .help("First day of the period, as YYYY-MM-DD"))
+            .arg(Arg::with_name("to")
+                .long("to")
+                .takes_value(true)
+                .requires("from")
+                .help("Last day of the period, as YYYY-MM-DD (defaults to today)"))
+            .arg(tag_filter()))
+        .subcommand(SubCommand::with_name("export-timesheet")
+            .about("Export one CSV row per note, or per task and day, for billing")
+            .arg(Arg::with_name("from")
+                .long("from")
+                .required(true)
+                .takes_value(true)
+                .help("First day to export, as YYYY-MM-DD"))
+            .arg(Arg::with_name("to")
+                .long("to")
+                .takes_value(true)
+                .help("Last day to export, as YYYY-MM-DD (defaults to today)"))
+            .arg(Arg::with_name("per-day")
+                .long("per-day")
+                .help("Sum the notes of each task per day instead of listing them")))
+        .subcommand(SubCommand::with_name("search")
+            .about("Search task titles, descriptions and notes, most relevant first")
+            .arg(Arg::with_name("query")
+                .index(1)
+                .required(true)
+                .multiple(true)
+                .help("Words to look for"))
+            .arg(Arg::with_name("open")
+                .long("open")
+                .conflicts_with("closed")
+                .help("Only search open tasks"))
+            .arg(Arg::with_name("closed")
+                .long("closed")
+                .help("Only search finished tasks"))
+            .arg(Arg::with_name("from")
+                .long("from")
+                .takes_value(true)
+                .help("Only tasks created, or notes started, on or after this YYYY-MM-DD date"))
+            .arg(Arg::with_name("to")
+                .long("to")
+                .takes_value(true)
+                .requires("from")
+                .help("Only tasks created, or notes started, on or before this YYYY-MM-DD date")))
         .subcommand(SubCommand::with_name("review")
             .help("Review recently updated & finished tasks")
             .arg(Arg::with_name("days")
                 .index(1)
                 .required(true)
                 .takes_value(true)
-                .help("Number of days into the past to search for updated/finished tasks")));
+                .help("Number of days into the past to search for updated/finished tasks"))
+            .arg(tag_filter()))
+        .subcommand(SubCommand::with_name("tui")
+            .about("Browse the task tree and its notes full-screen, with keys to add tasks and \
+                    notes, run the timer, finish, reopen and move tasks"))
+}
+
+fn main() {
+    let app_matches = app().get_matches();
+    if let Err(err) = run(&app_matches) {
+        eprintln!("Error: {}", err);
+        std::process::exit(1);
+    }
+}
 
-    let app_matches = app.get_matches();
+fn run(app_matches: &ArgMatches) -> Result<()> {
     let subcommand = app_matches
         .subcommand_name()
         .expect("Please use one of the subcommands");
     let matches = app_matches.subcommand_matches(subcommand).unwrap();
 
+    let conn = connect_db()?;
+    let snapshot = if global_flag(app_matches, "dry-run") {
+        Some(MemoryStore::snapshot(&*conn)?)
+    } else {
+        None
+    };
+    let format = parse_format(app_matches);
+    if format == Format::Csv && subcommand != "export-timesheet" {
+        return Err(Error::Validation(format!("{} cannot print CSV", subcommand)));
+    }
+    let json = matches.is_present("json") || format != Format::Text;
+    let renderer = Renderer::stdout();
+    let store: &dyn Store = match snapshot {
+        Some(ref snapshot) => snapshot,
+        None => &*conn,
+    };
+
     match subcommand {
-        "init" => init(),
+        "migrate" => {
+            if matches.is_present("status") {
+                migrate_status(store)?;
+            } else {
+                let target = match matches.value_of("to") {
+                    Some(s) => Some(parse_int(s, "Schema version")?),
+                    None => None,
+                };
+                migrate(store, target)?;
+            }
+        }
         "new-task" => {
-            let parent_id_str = matches.value_of("parent");
-            let parent_id = match parent_id_str {
-                Some(s) => Some(i32::from_str(s).expect("Cannot cast to i32")),
+            let parent_id = match matches.value_of("parent") {
+                Some(s) => Some(parse_int(s, "Parent task's ID")?),
                 None => None,
             };
             let template = matches.value_of("template");
-            new_task(parent_id,
-                     matches.value_of("title"),
-                     matches.value_of("body"),
-                     template)
+            let priority = match matches.value_of("priority") {
+                Some(s) => Some(parse_int(s, "Priority")?),
+                None => None,
+            };
+            let due_date = match matches.value_of("due") {
+                Some(s) => Some(period::parse_day(s, Local::today())?.naive_local()),
+                None => None,
+            };
+            // checked up front so that a bad rule does not leave a task behind
+            if let Some(recurrence) = matches.value_of("repeat") {
+                Recurrence::parse(recurrence)?;
+            }
+            let mut task = new_task(store,
+                                    parent_id,
+                                    matches.value_of("title"),
+                                    matches.value_of("body"),
+                                    template,
+                                    priority,
+                                    due_date)?;
+            if let Some(recurrence) = matches.value_of("repeat") {
+                task.series_id = Some(Task::repeat(store, task.id, recurrence)?.id);
+            }
+            print_created(&task, task.id, json);
         }
         "tree" => {
-            // TODO add argument to specify root node
-            let open = matches.is_present("open");
-            tree(open)
+            let mut options = TreeOptions::default();
+            if let Some(root) = matches.value_of("root") {
+                options.root = Some(parse_int(root, "Root task's ID")?);
+            }
+            if let Some(depth) = matches.value_of("depth") {
+                options.depth = Some(parse_int(depth, "Depth")?);
+            }
+            if let Some(since) = matches.value_of("since") {
+                options.since = Some(period::parse_day(since, Local::today())?.and_hms(0, 0, 0));
+            }
+            options.open_only = matches.is_present("open");
+            options.closed_only = matches.is_present("closed-only");
+            options.tags = tag_filters(matches);
+            options.ancestors = matches.is_present("ancestors");
+            options.time = matches.is_present("time");
+            options.order = TreeOrder::named(matches.value_of("sort").unwrap())?;
+            tree(store, &options, format, &renderer)?;
         }
         "view-task" => {
-            let task_id_str = matches.value_of("task").unwrap();
-            let task_id = i32::from_str(task_id_str).expect("Cannot cast to i32");
-
-            view_task(task_id)
+            let task_id = parse_int(matches.value_of("task").unwrap(), "Task's ID")?;
+            view_task(store, task_id, format, &renderer)?;
         }
-        "leaves" => show_leaves(),
+        "leaves" => {
+            let order = TaskOrder::named(matches.value_of("sort").unwrap())?;
+            let blocked = if matches.is_present("ready") || matches.is_present("blocked") {
+                Some(matches.is_present("blocked"))
+            } else {
+                None
+            };
+            show_leaves(store, order, &tag_filters(matches), blocked, format, &renderer)?;
+        }
+        "agenda" => agenda(store, format, &renderer)?,
         "new-note" => {
-            let task_id_str = matches.value_of("task").unwrap();
-            let task_id = i32::from_str(task_id_str).expect("Cannot cast to i32");
+            let task_id = parse_int(matches.value_of("task").unwrap(), "Task's ID")?;
             let template = matches.value_of("template");
-            new_note(task_id, template);
+            let times = note_times(matches)?;
+            let note = new_note(store, task_id, template, times)?;
+            print_created(&note, note.id, json);
+        }
+        "start" => {
+            let task_id = parse_int(matches.value_of("task").unwrap(), "Task's ID")?;
+            start(store, task_id)?;
+        }
+        "stop" => {
+            let note = stop(store, matches.value_of("note"), matches.value_of("template"))?;
+            print_created(&note, note.id, json);
+        }
+        "status" => status(store, format)?,
+        "edit-task" => {
+            let task_id = parse_int(matches.value_of("task").unwrap(), "Task's ID")?;
+            edit_task(store, task_id)?;
+        }
+        "edit-note" => {
+            let note_id = parse_int(matches.value_of("note").unwrap(), "Note's ID")?;
+            edit_note(store, note_id)?;
         }
         "finish" => {
-            let task_id_str = matches.value_of("task").unwrap();
-            let task_id = i32::from_str(task_id_str).expect("Cannot cast to i32");
-            finish(task_id);
+            let task_id = parse_int(matches.value_of("task").unwrap(), "Task's ID")?;
+            finish(store, task_id)?;
+        }
+        "move" => {
+            let task_id = parse_int(matches.value_of("task").unwrap(), "Task's ID")?;
+            let parent_id = match matches.value_of("parent") {
+                Some(parent) => Some(parse_int(parent, "Parent task's ID")?),
+                None => None,
+            };
+            move_task(store, task_id, parent_id)?;
+        }
+        "block" => {
+            let task_id = parse_int(matches.value_of("task").unwrap(), "Task's ID")?;
+            let blocker_id = parse_int(matches.value_of("on").unwrap(), "Blocking task's ID")?;
+            block(store, task_id, blocker_id, matches.is_present("remove"))?;
+        }
+        "repeat" => {
+            let task_id = parse_int(matches.value_of("task").unwrap(), "Task's ID")?;
+            let recurrence = matches.values_of("recurrence")
+                .map(|words| words.collect::<Vec<_>>().join(" "));
+            repeat(store, task_id, recurrence.as_deref())?;
+        }
+        "tag" => {
+            let task_id = parse_int(matches.value_of("task").unwrap(), "Task's ID")?;
+            let changes: Vec<&str> = matches.values_of("changes").unwrap().collect();
+            tag(store, task_id, &changes)?;
+        }
+        "reopen" => {
+            let task_id = parse_int(matches.value_of("task").unwrap(), "Task's ID")?;
+            reopen(store, task_id)?;
         }
         "new-template" => {
             let name = matches.value_of("name").unwrap();
-            new_template(name);
+            new_template(store, name)?;
+        }
+        "report" => {
+            let today = Local::today();
+            let period = match matches.value_of("period") {
+                Some(name) => Period::named(name, today)?,
+                None => {
+                    Period::between(matches.value_of("from").unwrap(),
+                                    matches.value_of("to"),
+                                    today)?
+                }
+            };
+            report(store, &period, &tag_filters(matches), format, &renderer)?;
+        }
+        "export-timesheet" => {
+            let period = Period::between(matches.value_of("from").unwrap(),
+                                         matches.value_of("to"),
+                                         Local::today())?;
+            export_timesheet(store, &period, matches.is_present("per-day"), format)?;
+        }
+        "search" => {
+            let query: Vec<&str> = matches.values_of("query").unwrap().collect();
+            let mut filter = SearchFilter::default();
+            if matches.is_present("open") || matches.is_present("closed") {
+                filter.open = Some(matches.is_present("open"));
+            }
+            if let Some(from) = matches.value_of("from") {
+                let period = Period::between(from, matches.value_of("to"), Local::today())?;
+                filter.from = Some(period.start()?);
+                filter.to = Some(period.end()?);
+            }
+            search(store, &query.join(" "), &filter, format, &renderer)?;
         }
         "review" => {
-            let days_str = matches.value_of("days").unwrap();
-            let days = i32::from_str(days_str).expect("Cannot cast to i32");
-            review(days);
+            let days = parse_int(matches.value_of("days").unwrap(), "Number of days")?;
+            review(store, days, &tag_filters(matches), format, &renderer)?;
         }
+        "tui" => tui::run(store)?,
         _ => {}
     }
+
+    if let Some(snapshot) = snapshot {
+        println!("Dry run, nothing was written. This command would:");
+        for change in snapshot.changes() {
+            println!("  - {}", change);
+        }
+    }
+    Ok(())
+}
+#[test]
+fn test_dry_run_after_subcommand() {
+    use std::{env, fs};
+
+    let path = env::temp_dir().join(format!("todo-dry-run-{}.db", std::process::id()));
+    let _ = fs::remove_file(&path);
+    env::set_var("DATABASE_URL", format!("sqlite://{}", path.display()));
+    let todo = |args: &[&str]| run(&app().get_matches_from(args)).unwrap();
+    todo(&["todo", "migrate"]);
+    todo(&["todo", "new-task", "--title", "Parent", "--body", ""]);
+    todo(&["todo", "new-task", "1", "--title", "Child", "--body", ""]);
+    todo(&["todo", "finish", "2", "--dry-run"]);
+    todo(&["todo", "--dry-run", "finish", "1"]);
+
+    let store = connect_db().unwrap();
+    assert!(Task::find(&*store, 1).unwrap().open && Task::find(&*store, 2).unwrap().open);
+    drop(store);
+    fs::remove_file(&path).unwrap();
 }
@@ -0,0 +1,88 @@
+use std;
+use std::fmt;
+use std::process::ExitStatus;
+
+use postgres;
+use rusqlite;
+
+#[derive(Debug)]
+pub enum Error {
+    Postgres(postgres::error::Error),
+    PostgresConnect(postgres::error::ConnectError),
+    Sqlite(rusqlite::Error),
+    IO(std::io::Error),
+    Env(std::env::VarError),
+    /// `$EDITOR` exited unsuccessfully
+    Subprocess(ExitStatus),
+    /// A task, note or template that was referred to does not exist. Carries a description of it,
+    /// such as "task 42".
+    NotFound(String),
+    /// Input that was rejected before reaching the database
+    Validation(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn not_found<K: fmt::Display>(kind: &str, key: K) -> Error {
+        Error::NotFound(format!("{} {}", kind, key))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Postgres(ref err) => write!(f, "Database error: {}", err),
+            Error::PostgresConnect(ref err) => write!(f, "Cannot connect to the database: {}", err),
+            Error::Sqlite(ref err) => write!(f, "SQLite error: {}", err),
+            Error::IO(ref err) => write!(f, "IO error: {}", err),
+            Error::Env(ref err) => write!(f, "Environment error: {}", err),
+            Error::Subprocess(ref status) => write!(f, "The editor exited with {}", status),
+            Error::NotFound(ref what) => write!(f, "{} does not exist", what),
+            Error::Validation(ref reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match *self {
+            Error::Postgres(ref err) => Some(err),
+            Error::PostgresConnect(ref err) => Some(err),
+            Error::Sqlite(ref err) => Some(err),
+            Error::IO(ref err) => Some(err),
+            Error::Env(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<postgres::error::Error> for Error {
+    fn from(err: postgres::error::Error) -> Error {
+        Error::Postgres(err)
+    }
+}
+
+impl From<postgres::error::ConnectError> for Error {
+    fn from(err: postgres::error::ConnectError) -> Error {
+        Error::PostgresConnect(err)
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(err: rusqlite::Error) -> Error {
+        Error::Sqlite(err)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Error {
+        Error::IO(err)
+    }
+}
+
+impl From<std::env::VarError> for Error {
+    fn from(err: std::env::VarError) -> Error {
+        Error::Env(err)
+    }
+}
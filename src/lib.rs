@@ -1,34 +1,44 @@
 extern crate postgres;
 extern crate mktemp;
 extern crate chrono;
+#[macro_use]
+extern crate rusqlite;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate termion;
 
+pub mod agenda;
+pub mod error;
+pub mod migrations;
 pub mod models;
+pub mod period;
+pub mod recurrence;
+pub mod render;
+pub mod store;
+pub mod timesheet;
+pub mod tui;
 pub mod utils;
 
+pub use error::{Error, Result};
+pub use store::Store;
+use store::sqlite::SqliteStore;
+
 use postgres::{Connection, TlsMode};
 use std::env::var;
 use std::fs::File;
 use std::io::prelude::*;
 
-#[derive(Debug)]
-pub enum Error {
-    IO,
-    Env,
-}
-
-impl From<std::env::VarError> for Error {
-    fn from(_: std::env::VarError) -> Error {
-        Error::Env
-    }
-}
+const SQLITE_SCHEME: &str = "sqlite://";
 
-impl From<std::io::Error> for Error {
-    fn from(_: std::io::Error) -> Error {
-        Error::IO
+fn expand_home(path: &str) -> String {
+    match var("HOME") {
+        Ok(ref home) if path.starts_with("~/") => format!("{}{}", home, &path[1..]),
+        _ => path.to_owned(),
     }
 }
 
-fn read_todorc() -> Result<String, Error> {
+fn read_todorc() -> Result<String> {
     let home = var("HOME")?;
     let filename = format!("{}/.todorc", home);
     let mut f = File::open(filename)?;
@@ -37,10 +47,18 @@ fn read_todorc() -> Result<String, Error> {
     Ok(s.trim().to_owned())
 }
 
-pub fn connect_db() -> Connection {
+/// Opens the store named by `$DATABASE_URL`, falling back to `~/.todorc`.
+///
+/// A `sqlite://<path>` URL selects the embedded SQLite backend, anything else is handed to
+/// Postgres.
+pub fn connect_db() -> Result<Box<dyn Store>> {
     let db_url = match var("DATABASE_URL") {
         Ok(url) => url,
-        Err(_) => read_todorc().expect("$DATABASE_URL is not set"),
+        Err(err) => read_todorc().map_err(|_| Error::Env(err))?,
     };
-    Connection::connect(db_url, TlsMode::None).expect("Failed to establish database connection")
+    if let Some(path) = db_url.strip_prefix(SQLITE_SCHEME) {
+        Ok(Box::new(SqliteStore::open(expand_home(path))?))
+    } else {
+        Ok(Box::new(Connection::connect(db_url, TlsMode::None)?))
+    }
 }
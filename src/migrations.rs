@@ -0,0 +1,86 @@
+use error::Result;
+use store::Store;
+
+/// One step of a backend's schema history.
+///
+/// `up` and `down` are SQL scripts in the backend's own dialect. Versions start at 1 and are
+/// applied in ascending order; version 0 is an empty database.
+pub struct Migration {
+    pub version: i32,
+    pub name: &'static str,
+    pub up: &'static str,
+    pub down: &'static str,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    Up,
+    Down,
+}
+
+pub struct MigrationStatus {
+    pub version: i32,
+    pub name: &'static str,
+    pub applied: bool,
+}
+
+pub fn latest_version(store: &dyn Store) -> i32 {
+    store.migrations().iter().map(|m| m.version).max().unwrap_or(0)
+}
+
+pub fn status(store: &dyn Store) -> Result<Vec<MigrationStatus>> {
+    let applied = store.applied_migrations()?;
+    let result = store.migrations()
+        .iter()
+        .map(|m| {
+            MigrationStatus {
+                version: m.version,
+                name: m.name,
+                applied: applied.contains(&m.version),
+            }
+        })
+        .collect();
+    Ok(result)
+}
+
+/// Moves the schema to `target` (the latest version if `None`), returning the steps that were
+/// run in the order they were run.
+pub fn migrate(store: &dyn Store,
+               target: Option<i32>)
+               -> Result<Vec<(Direction, &'static Migration)>> {
+    let migrations = store.migrations();
+    let applied = store.applied_migrations()?;
+    let target = target.unwrap_or_else(|| latest_version(store));
+
+    let mut steps = vec![];
+    for migration in migrations.iter() {
+        if migration.version <= target && !applied.contains(&migration.version) {
+            steps.push((Direction::Up, migration));
+        }
+    }
+    for migration in migrations.iter().rev() {
+        if migration.version > target && applied.contains(&migration.version) {
+            steps.push((Direction::Down, migration));
+        }
+    }
+
+    for &(direction, migration) in &steps {
+        store.apply_migration(migration, direction)?;
+    }
+    Ok(steps)
+}
+#[test]
+fn test_migrate_up_and_down() {
+    use store::sqlite::SqliteStore;
+
+    let store = SqliteStore::open_in_memory().unwrap();
+    let latest = latest_version(&store);
+    assert_eq!(migrate(&store, None).unwrap().len(), latest as usize);
+    assert!(migrate(&store, None).unwrap().is_empty());
+    assert!(status(&store).unwrap().iter().all(|m| m.applied));
+
+    let steps = migrate(&store, Some(0)).unwrap();
+    assert!(steps.iter().all(|&(direction, _)| direction == Direction::Down));
+    assert_eq!(steps.first().map(|&(_, m)| m.version), Some(latest));
+    assert!(store.applied_migrations().unwrap().is_empty());
+}
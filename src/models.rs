@@ -1,149 +1,374 @@
 use std;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 use chrono::*;
-use postgres::Connection;
-use postgres::rows::Row;
+use serde::Serializer;
 
-#[derive(Debug, Clone)]
+use error::{Error, Result};
+use recurrence::Recurrence;
+use store::Store;
+
+#[derive(Debug, Clone, Serialize)]
 pub struct Task {
     pub id: i32,
     pub parent_id: Option<i32>,
     pub title: String,
     pub body: String,
     pub open: bool,
+    #[serde(serialize_with = "serialize_date")]
     pub date_created: DateTime<Local>,
+    #[serde(serialize_with = "serialize_optional_date")]
+    pub date_finished: Option<DateTime<Local>>,
+    /// From `HIGHEST_PRIORITY` to `LOWEST_PRIORITY`
+    pub priority: Option<i32>,
+    #[serde(serialize_with = "serialize_optional_day")]
+    pub due_date: Option<NaiveDate>,
+    /// Set on every instance of a recurring task
+    pub series_id: Option<i32>,
+    /// Sorted, without duplicates
+    pub tags: Vec<String>,
 }
 
-#[derive(Debug, Clone)]
+pub const HIGHEST_PRIORITY: i32 = 1;
+pub const LOWEST_PRIORITY: i32 = 5;
+
+/// The orders accepted by `TaskOrder::named`
+pub const TASK_ORDER_NAMES: &[&str] = &["created", "due", "priority"];
+
+/// How `Task::open_leaves` sorts its tasks. Tasks without a due date or a priority come after
+/// those with one, and ties go to the newest task.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TaskOrder {
+    /// Newest first
+    Created,
+    /// Earliest due date first, then by priority
+    Due,
+    /// Highest priority first, then by due date
+    Priority,
+}
+
+/// The orders accepted by `TreeOrder::named`
+pub const TREE_ORDER_NAMES: &[&str] = &["id", "created", "title", "priority", "activity"];
+
+/// How `Task::sorted_subtree` sorts the roots and the children of each task. Ties go to the
+/// lowest ID, so a tree always comes out in the same order.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum TreeOrder {
+    #[default]
+    Id,
+    /// Newest first
+    Created,
+    /// Alphabetical, ignoring case
+    Title,
+    /// Highest priority first, tasks without one last
+    Priority,
+    /// Most recent first, by the latest creation, finish or note start within each subtree
+    Activity,
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct TaskAux {
     pub id: i32,
     pub parent_id: Option<i32>,
     pub title: String,
     pub body: String,
     pub open: bool,
+    #[serde(serialize_with = "serialize_date")]
     pub date_created: DateTime<Local>,
+    #[serde(serialize_with = "serialize_optional_date")]
+    pub date_finished: Option<DateTime<Local>>,
+    pub priority: Option<i32>,
+    #[serde(serialize_with = "serialize_optional_day")]
+    pub due_date: Option<NaiveDate>,
+    pub series_id: Option<i32>,
+    pub tags: Vec<String>,
+    /// Time spent in this task's own notes
     pub duration_seconds: f32,
+    /// Time spent in this task's notes and in the notes of all of its descendants
+    pub total_duration_seconds: f32,
 }
 
-#[derive(Debug)]
+/// A task within a subtree, as returned by `Task::subtree`
+#[derive(Debug, Clone, Serialize)]
+pub struct TreeNode {
+    pub task: Task,
+    /// 0 for the root of the subtree
+    pub depth: i32,
+    /// Task IDs from the root of the subtree down to this task
+    pub path: Vec<i32>,
+}
+
+#[derive(Debug, Serialize)]
 pub struct Note {
     pub id: i32,
     pub task_id: i32,
     pub body: String,
+    #[serde(serialize_with = "serialize_date")]
     pub date_start: DateTime<Local>,
+    #[serde(serialize_with = "serialize_date")]
     pub date_end: DateTime<Local>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct NoteAux {
     pub id: i32,
     pub task_id: i32,
     pub body: String,
+    #[serde(serialize_with = "serialize_date")]
     pub date_start: DateTime<Local>,
+    #[serde(serialize_with = "serialize_date")]
     pub date_end: DateTime<Local>,
     pub duration_seconds: f32,
 }
 
+/// The notes written for one task within a report's period
+#[derive(Debug, Serialize)]
+pub struct TaskReport {
+    pub task: Task,
+    pub notes: Vec<NoteAux>,
+    pub duration_seconds: f32,
+}
+
+/// Narrows down `Task::search`. Dates apply to a task's creation and a note's start.
+#[derive(Debug, Clone, Default)]
+pub struct SearchFilter {
+    pub open: Option<bool>,
+    pub from: Option<DateTime<Local>>,
+    pub to: Option<DateTime<Local>>,
+}
+
+impl SearchFilter {
+    pub fn matches(&self, open: bool, date: DateTime<Local>) -> bool {
+        self.open.is_none_or(|o| o == open) && self.from.is_none_or(|from| date >= from) &&
+        self.to.is_none_or(|to| date < to)
+    }
+}
+
+/// A task, or one of its notes, matching a search. `snippet` marks the matched words with `*`.
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchResult {
+    pub task_id: i32,
+    pub task_title: String,
+    pub open: bool,
+    pub note_id: Option<i32>,
+    pub snippet: String,
+    pub rank: f32,
+    #[serde(serialize_with = "serialize_date")]
+    pub date: DateTime<Local>,
+}
+
+/// The work in progress started by `todo start`. At most one runs at a time.
+#[derive(Debug, Clone, Serialize)]
+pub struct Timer {
+    pub task_id: i32,
+    #[serde(serialize_with = "serialize_date")]
+    pub date_start: DateTime<Local>,
+}
+
+/// Work on `task_id` cannot start until `blocker_id` is finished. Unlike `parent_id`, this may
+/// link tasks in different subtrees.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Dependency {
+    pub task_id: i32,
+    pub blocker_id: i32,
+}
+
+/// What the instances of a recurring task share: how often they come back (see
+/// `Recurrence::parse`) and the body each new instance starts from.
+#[derive(Debug, Clone, Serialize)]
+pub struct Series {
+    pub id: i32,
+    pub recurrence: String,
+    pub body: String,
+}
+
 #[derive(Debug, Clone)]
 pub struct Template {
     pub name: String,
     pub body: String,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
+/// A note written, or a task finished, within a review window. Tasks that were finished without
+/// any note in the window appear once, with no note.
 pub struct Review {
     pub task_id: i32,
     pub task_title: String,
     pub open: bool,
-    pub note_id: i32,
-    pub note_body: String,
+    #[serde(serialize_with = "serialize_optional_date")]
+    pub date_finished: Option<DateTime<Local>>,
+    pub note_id: Option<i32>,
+    pub note_body: Option<String>,
+    #[serde(serialize_with = "serialize_date")]
     pub last_updated: DateTime<Local>,
 }
 
-pub fn create_tables(conn: &Connection) {
-    Task::create_table(conn);
-    Note::create_table(conn);
-    Template::create_table(conn);
+/// Timestamps are serialized as RFC 3339 strings.
+pub(crate) fn serialize_date<S: Serializer>(date: &DateTime<Local>,
+                                            serializer: S)
+                                            -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_str(&date.to_rfc3339())
 }
 
-pub fn drop_tables(conn: &Connection) {
-    Task::drop_table(conn);
-    Note::drop_table(conn);
-    Template::drop_table(conn);
+fn serialize_optional_date<S: Serializer>(date: &Option<DateTime<Local>>,
+                                          serializer: S)
+                                          -> std::result::Result<S::Ok, S::Error> {
+    match *date {
+        Some(ref date) => serialize_date(date, serializer),
+        None => serializer.serialize_none(),
+    }
 }
 
-impl Template {
-    pub fn create_table(conn: &Connection) {
-        conn.execute("
-CREATE TABLE IF NOT EXISTS template (
-    name    TEXT PRIMARY KEY,
-    body    TEXT NOT NULL
-);
-",
-                     &[])
-            .unwrap();
+/// Dates without a time are serialized as YYYY-MM-DD strings.
+fn serialize_optional_day<S: Serializer>(date: &Option<NaiveDate>,
+                                         serializer: S)
+                                         -> std::result::Result<S::Ok, S::Error> {
+    match *date {
+        Some(ref date) => serializer.serialize_str(&date.format("%Y-%m-%d").to_string()),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Orders `Some` values before `None`.
+fn cmp_some_first<T: Ord>(a: &Option<T>, b: &Option<T>) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => a.cmp(b),
+        (&Some(_), &None) => Ordering::Less,
+        (&None, &Some(_)) => Ordering::Greater,
+        (&None, &None) => Ordering::Equal,
     }
+}
 
-    pub fn drop_table(conn: &Connection) {
-        conn.execute("DROP TABLE IF EXISTS template CASCADE", &[])
-            .unwrap();
+impl TaskOrder {
+    pub fn named(name: &str) -> Result<TaskOrder> {
+        match name {
+            "created" => Ok(TaskOrder::Created),
+            "due" => Ok(TaskOrder::Due),
+            "priority" => Ok(TaskOrder::Priority),
+            _ => {
+                Err(Error::Validation(format!("Unknown order {:?}, expected one of {}",
+                                              name,
+                                              TASK_ORDER_NAMES.join(", "))))
+            }
+        }
     }
 
-    pub fn upsert(conn: &Connection, name: &str, body: &str) {
-        conn.execute("INSERT INTO template(name, body) VALUES ($1, $2) ON CONFLICT (name) DO \
-                      UPDATE SET BODY = $2",
-                     &[&name, &body])
-            .unwrap();
+    pub fn compare(&self, a: &Task, b: &Task) -> Ordering {
+        let priority = cmp_some_first(&a.priority, &b.priority);
+        let due = cmp_some_first(&a.due_date, &b.due_date);
+        let created = b.date_created.cmp(&a.date_created);
+        match *self {
+            TaskOrder::Created => created,
+            TaskOrder::Due => due.then(priority).then(created),
+            TaskOrder::Priority => priority.then(due).then(created),
+        }
     }
+}
+#[test]
+fn test_task_order() {
+    let now = Local::now();
+    let task = |id, priority, due: Option<u32>| {
+        let mut task = Task::new(None, "", "", now + Duration::seconds(id));
+        task.id = id as i32;
+        task.priority = priority;
+        task.due_date = due.map(|day| NaiveDate::from_ymd(2017, 3, day));
+        task
+    };
+    let mut tasks = vec![task(1, None, None),
+                         task(2, Some(3), Some(20)),
+                         task(3, Some(1), None),
+                         task(4, None, Some(10)),
+                         task(5, Some(3), Some(10))];
+    let ids = |tasks: &[Task]| tasks.iter().map(|task| task.id).collect::<Vec<_>>();
+
+    tasks.sort_by(|a, b| TaskOrder::Due.compare(a, b));
+    assert_eq!(ids(&tasks), vec![5, 4, 2, 3, 1]);
+    tasks.sort_by(|a, b| TaskOrder::Priority.compare(a, b));
+    assert_eq!(ids(&tasks), vec![3, 5, 2, 4, 1]);
+    tasks.sort_by(|a, b| TaskOrder::Created.compare(a, b));
+    assert_eq!(ids(&tasks), vec![5, 4, 3, 2, 1]);
+    assert!(TaskOrder::named("alphabetical").is_err());
+}
 
-    // returns the body
-    pub fn existing(conn: &Connection, name: &str) -> Option<String> {
-        let rows = &conn.query("SELECT body FROM template WHERE name = $1", &[&name])
-                        .unwrap();
-
-        if rows.len() != 1 {
-            None
-        } else {
-            let row = rows.get(0);
-            Some(row.get(0))
+impl TreeOrder {
+    pub fn named(name: &str) -> Result<TreeOrder> {
+        match name {
+            "id" => Ok(TreeOrder::Id),
+            "created" => Ok(TreeOrder::Created),
+            "title" => Ok(TreeOrder::Title),
+            "priority" => Ok(TreeOrder::Priority),
+            "activity" => Ok(TreeOrder::Activity),
+            _ => {
+                Err(Error::Validation(format!("Unknown order {:?}, expected one of {}",
+                                              name,
+                                              TREE_ORDER_NAMES.join(", "))))
+            }
         }
     }
+}
 
-    pub fn all(conn: &Connection) -> Vec<Template> {
-        let mut result = vec![];
-        for row in &conn.query("SELECT name, body FROM template ORDER BY name", &[])
-                        .unwrap() {
-            let r = &mut result;
-            r.push(Template {
-                       name: row.get(0),
-                       body: row.get(1),
-                   });
+/// Reorders the depth-first `nodes` so that each set of siblings follows `compare`, keeping
+/// every task directly above its descendants.
+fn sort_siblings<F>(nodes: Vec<TreeNode>, compare: F) -> Vec<TreeNode>
+    where F: Fn(&TreeNode, &TreeNode) -> Ordering
+{
+    let mut roots = vec![];
+    let mut children = vec![Vec::new(); nodes.len()];
+    let mut ancestors: Vec<usize> = vec![];
+    for (i, node) in nodes.iter().enumerate() {
+        ancestors.truncate(node.depth as usize);
+        match ancestors.last() {
+            Some(&parent) => children[parent].push(i),
+            None => roots.push(i),
         }
-        result
+        ancestors.push(i);
+    }
+    roots.sort_by(|&a, &b| compare(&nodes[a], &nodes[b]));
+    for siblings in &mut children {
+        siblings.sort_by(|&a, &b| compare(&nodes[a], &nodes[b]));
+    }
+
+    let mut order = Vec::with_capacity(nodes.len());
+    let mut stack: Vec<usize> = roots.into_iter().rev().collect();
+    while let Some(i) = stack.pop() {
+        order.push(i);
+        stack.extend(children[i].iter().rev());
     }
+    let mut nodes: Vec<Option<TreeNode>> = nodes.into_iter().map(Some).collect();
+    order.into_iter().filter_map(|i| nodes[i].take()).collect()
 }
 
-impl Task {
-    pub fn create_table(conn: &Connection) {
-        conn.execute("
-CREATE TABLE IF NOT EXISTS task (
-    id              SERIAL PRIMARY KEY,
-    parent_id       INTEGER REFERENCES task(id) ON DELETE CASCADE,
-    title           TEXT NOT NULL,
-    body            TEXT NOT NULL,
-    open            BOOL NOT NULL DEFAULT TRUE,
-    date_created    TIMESTAMP WITH TIME ZONE NOT NULL
-);
-",
-                     &[])
-            .unwrap();
-    }
-
-    pub fn drop_table(conn: &Connection) {
-        conn.execute("DROP TABLE IF EXISTS task CASCADE", &[])
-            .unwrap();
+impl Series {
+    pub fn find(store: &dyn Store, id: i32) -> Result<Series> {
+        store.find_series(id)?.ok_or_else(|| Error::not_found("series", id))
+    }
+}
+
+impl Template {
+    pub fn upsert(store: &dyn Store, name: &str, body: &str) -> Result<()> {
+        if name.trim().is_empty() {
+            return Err(Error::Validation("A template needs a name".to_owned()));
+        }
+        store.upsert_template(name, body)
     }
 
+    // returns the body
+    pub fn existing(store: &dyn Store, name: &str) -> Result<Option<String>> {
+        store.find_template(name)
+    }
+
+    // returns the body, failing if there is no such template
+    pub fn find(store: &dyn Store, name: &str) -> Result<String> {
+        Template::existing(store, name)?.ok_or_else(|| Error::not_found("template", name))
+    }
+
+    pub fn all(store: &dyn Store) -> Result<Vec<Template>> {
+        store.all_templates()
+    }
+}
+
+impl Task {
     pub fn new(parent_id: Option<i32>,
                title: &str,
                body: &str,
@@ -151,238 +376,545 @@ CREATE TABLE IF NOT EXISTS task (
                -> Self {
         Task {
             id: 0,
-            parent_id: parent_id,
+            parent_id,
             title: title.to_owned(),
             body: body.to_owned(),
             open: true,
-            date_created: date_created,
+            date_created,
+            date_finished: None,
+            priority: None,
+            due_date: None,
+            series_id: None,
+            tags: vec![],
         }
     }
 
-    pub fn create(self, conn: &Connection) {
-        // TODO Returns the ID
-        conn.execute("INSERT INTO task(parent_id, title, body, date_created) VALUES ($1, $2, $3, \
-                      $4)",
-                     &[&self.parent_id, &self.title, &self.body, &self.date_created])
-            .unwrap();
+    pub fn has_tags(&self, tags: &[String]) -> bool {
+        tags.iter().all(|tag| self.tags.contains(tag))
     }
 
-    pub fn delete(conn: &Connection, id: i32) {
-        conn.execute("DELETE FROM task WHERE id = $1", &[&id])
-            .unwrap();
+    /// Inserts the task, returning the stored row with its new ID.
+    pub fn create(mut self, store: &dyn Store) -> Result<Task> {
+        if self.title.trim().is_empty() {
+            return Err(Error::Validation("A task needs a title".to_owned()));
+        }
+        if let Some(priority) = self.priority {
+            if !(HIGHEST_PRIORITY..=LOWEST_PRIORITY).contains(&priority) {
+                return Err(Error::Validation(format!("A priority goes from {} (highest) to {} \
+                                                      (lowest), got {}",
+                                                     HIGHEST_PRIORITY,
+                                                     LOWEST_PRIORITY,
+                                                     priority)));
+            }
+        }
+        for tag in &self.tags {
+            validate_tag(tag)?;
+        }
+        self.tags.sort();
+        self.tags.dedup();
+        store.create_task(&self)
     }
 
-    pub fn finish(conn: &Connection, id: i32) {
-        // TODO Check how many rows were affected
-        conn.execute("UPDATE task SET open = FALSE WHERE id = $1", &[&id])
-            .unwrap();
+    /// Applies changes such as `+work` (or just `work`) to add a tag and `-urgent` to remove one.
+    pub fn tag(store: &dyn Store, id: i32, changes: &[&str]) -> Result<()> {
+        Task::find(store, id)?;
+        let mut add = vec![];
+        let mut remove = vec![];
+        for change in changes {
+            if let Some(tag) = change.strip_prefix('-') {
+                remove.push(tag.to_owned());
+            } else if let Some(tag) = change.strip_prefix('+') {
+                add.push(tag.to_owned());
+            } else {
+                add.push(change.to_string());
+            }
+        }
+        for tag in add.iter().chain(remove.iter()) {
+            validate_tag(tag)?;
+        }
+        store.update_tags(id, &add, &remove)
     }
 
-    pub fn unpack(row: Row) -> Task {
-        Task {
-            id: row.get(0),
-            parent_id: row.get(1),
-            title: row.get(2),
-            body: row.get(3),
-            open: row.get(4),
-            date_created: row.get(5),
+    pub fn delete(store: &dyn Store, id: i32) -> Result<()> {
+        store.delete_task(id)
+    }
+
+    pub fn update(store: &dyn Store, id: i32, title: &str, body: &str) -> Result<()> {
+        if title.trim().is_empty() {
+            return Err(Error::Validation("A task needs a title".to_owned()));
         }
+        store.update_task(id, title, body)
     }
 
-    pub fn all(conn: &Connection) -> Vec<Task> {
-        let mut result = vec![];
-        for row in &conn.query("
-SELECT id, parent_id, title, body, open, date_created
-FROM task
-ORDER BY date_created DESC",
-                               &[])
-                        .unwrap() {
-            let r = &mut result;
-            r.push(Task::unpack(row));
+    /// Moves the task and its subtree under `parent_id`, or to the root if `None`.
+    ///
+    /// Fails with `Error::Validation` if `parent_id` is the task itself or one of its descendants,
+    /// naming the chain of ancestors that would loop back to the task.
+    pub fn set_parent(store: &dyn Store, id: i32, parent_id: Option<i32>) -> Result<()> {
+        Task::find(store, id)?;
+        if parent_id == Some(id) {
+            return Err(Error::Validation(format!("Task {} cannot be its own parent", id)));
         }
-        result
-    }
-
-    pub fn find(conn: &Connection, id: i32) -> Option<Task> {
-        let rows = &conn.query("
-SELECT id, parent_id, title, body, open, date_created
-FROM task
-WHERE id = $1
-ORDER BY date_created DESC",
-                               &[&id])
-                        .unwrap();
-
-        if rows.len() != 1 {
-            None
-        } else {
-            let row = rows.get(0);
-            Some(Task::unpack(row))
+        if let Some(parent_id) = parent_id {
+            // walk up from the new parent; reaching `id` means it would be its own ancestor
+            let mut chain = vec![];
+            let mut ancestor = Some(parent_id);
+            while let Some(ancestor_id) = ancestor {
+                if chain.contains(&ancestor_id) {
+                    break;
+                }
+                chain.push(ancestor_id);
+                if ancestor_id == id {
+                    let chain: Vec<String> = chain.iter().map(|id| id.to_string()).collect();
+                    return Err(Error::Validation(format!("Cannot move task {} under task {}: \
+                                                          task {} is already an ancestor of \
+                                                          task {} ({})",
+                                                         id,
+                                                         parent_id,
+                                                         id,
+                                                         parent_id,
+                                                         chain.join(" -> "))));
+                }
+                ancestor = Task::find(store, ancestor_id)?.parent_id;
+            }
         }
+        store.set_parent(id, parent_id)
     }
 
-    pub fn find_recently_updated(conn: &Connection, days: i32) -> Vec<Review> {
-        let mut result = vec![];
-        for row in &conn.query("
-SELECT task.id, title AS task_title, open, note.id AS note_id, note.body AS note_body, note.date_start AS last_updated
-FROM task, note
-WHERE note.task_id = task.id AND note.date_start > now() - interval '1 days' * $1::int
-ORDER BY task.id DESC, note.id DESC, last_updated DESC",
-                               &[&days]).unwrap() {
-            let r = &mut result;
-            r.push(Review {
-                       task_id: row.get(0),
-                       task_title: row.get(1),
-                       open: row.get(2),
-                       note_id: row.get(3),
-                       note_body: row.get(4),
-                       last_updated: row.get(5),
-                   })
+    /// Makes `id` wait on `blocker_id`.
+    ///
+    /// Fails with `Error::Validation` if `blocker_id` already waits on `id`, directly or through
+    /// other tasks, naming the chain of dependencies that would loop back to the task.
+    pub fn block(store: &dyn Store, id: i32, blocker_id: i32) -> Result<()> {
+        Task::find(store, id)?;
+        Task::find(store, blocker_id)?;
+        if id == blocker_id {
+            return Err(Error::Validation(format!("Task {} cannot block itself", id)));
         }
-        result
-    }
-
-    pub fn find_aux(conn: &Connection, id: i32) -> Option<TaskAux> {
-        let rows = &conn.query("
-SELECT id, parent_id, title, body, open, date_created, EXTRACT(EPOCH FROM duration)::REAL
-FROM task, (
-    SELECT SUM(note.date_end - note.date_start) AS duration
-    FROM note WHERE note.task_id = $1
-    ) t
-WHERE id = $2 ORDER BY date_created DESC",
-                               &[&id, &id])
-                        .unwrap();
-
-        if rows.len() != 1 {
-            None
-        } else {
-            let row = rows.get(0);
-            let duration: Option<f32> = row.get(6);
-            let duration_seconds: f32 = duration.unwrap_or(0f32);
-            Some(TaskAux {
-                     id: row.get(0),
-                     parent_id: row.get(1),
-                     title: row.get(2),
-                     body: row.get(3),
-                     open: row.get(4),
-                     date_created: row.get(5),
-                     duration_seconds: duration_seconds,
-                 })
+        let mut blockers: BTreeMap<i32, Vec<i32>> = BTreeMap::new();
+        for dependency in store.all_dependencies()? {
+            blockers.entry(dependency.task_id)
+                .or_default()
+                .push(dependency.blocker_id);
         }
+        // search what `blocker_id` waits on; reaching `id` means the two would wait on each other
+        let mut reached_from: BTreeMap<i32, i32> = BTreeMap::new();
+        let mut queue = vec![blocker_id];
+        while let Some(current) = queue.pop() {
+            if current == id {
+                let mut chain = vec![id.to_string()];
+                let mut step = id;
+                while step != blocker_id {
+                    step = reached_from[&step];
+                    chain.push(step.to_string());
+                }
+                chain.reverse();
+                return Err(Error::Validation(format!("Cannot block task {} on task {}: task {} \
+                                                      already waits on task {} ({})",
+                                                     id,
+                                                     blocker_id,
+                                                     blocker_id,
+                                                     id,
+                                                     chain.join(" -> "))));
+            }
+            for next in blockers.get(&current).map_or(&[][..], |next| &next[..]) {
+                if *next != blocker_id && !reached_from.contains_key(next) {
+                    reached_from.insert(*next, current);
+                    queue.push(*next);
+                }
+            }
+        }
+        store.add_dependency(id, blocker_id)
     }
 
-    pub fn find_notes(conn: &Connection, id: i32) -> Vec<Note> {
-        let mut result = vec![];
-        for row in &conn.query("
-SELECT id, task_id, body, date_start, date_end
-FROM note
-WHERE task_id = $1
-ORDER BY date_start",
-                               &[&id])
-                        .unwrap() {
-            let r = &mut result;
-            r.push(Note {
-                       id: row.get(0),
-                       task_id: row.get(1),
-                       body: row.get(2),
-                       date_start: row.get(3),
-                       date_end: row.get(4),
-                   });
+    pub fn unblock(store: &dyn Store, id: i32, blocker_id: i32) -> Result<()> {
+        store.remove_dependency(id, blocker_id)
+    }
+
+    /// The unfinished blockers of every task that has any, by task ID.
+    pub fn open_blockers(store: &dyn Store) -> Result<BTreeMap<i32, Vec<i32>>> {
+        let open: HashSet<i32> = Task::all(store)?
+            .into_iter()
+            .filter(|task| task.open)
+            .map(|task| task.id)
+            .collect();
+        let mut result = BTreeMap::new();
+        for dependency in store.all_dependencies()? {
+            if open.contains(&dependency.blocker_id) {
+                result.entry(dependency.task_id)
+                    .or_insert_with(Vec::new)
+                    .push(dependency.blocker_id);
+            }
         }
-        result
-    }
-
-    pub fn find_notes_aux(conn: &Connection, id: i32) -> Vec<NoteAux> {
-        let mut result = vec![];
-        for row in &conn.query("
-SELECT id, task_id, body, date_start, date_end, EXTRACT(EPOCH FROM date_end - date_start)::REAL AS duration
-FROM note
-WHERE task_id = $1
-ORDER BY date_start",
-                               &[&id])
-                        .unwrap() {
-            let r = &mut result;
-            r.push(NoteAux {
-                       id: row.get(0),
-                       task_id: row.get(1),
-                       body: row.get(2),
-                       date_start: row.get(3),
-                       date_end: row.get(4),
-                       duration_seconds: row.get(5),
-                   });
+        Ok(result)
+    }
+
+    /// Makes the task recur, starting a new series whose instances start from the task's current
+    /// body.
+    pub fn repeat(store: &dyn Store, id: i32, recurrence: &str) -> Result<Series> {
+        Recurrence::parse(recurrence)?;
+        let task = Task::find(store, id)?;
+        store.create_series(id, recurrence.trim(), &task.body)
+    }
+
+    /// Stops the task from coming back when it is finished. Earlier instances keep their link to
+    /// the series.
+    pub fn stop_repeating(store: &dyn Store, id: i32) -> Result<()> {
+        store.set_series(id, None)
+    }
+
+    /// Closes the task. Finishing an already finished task keeps its original `date_finished`.
+    ///
+    /// Finishing an open instance of a series also creates the next instance, with the same
+    /// parent, title, priority and tags, due on the series' first day after both today and the
+    /// finished instance's due date, and returns it.
+    pub fn finish(store: &dyn Store, id: i32) -> Result<Option<Task>> {
+        let now = Local::now();
+        let task = Task::find(store, id)?;
+        let series = match task.series_id {
+            Some(series_id) if task.open => Series::find(store, series_id)?,
+            _ => {
+                store.finish_task(id, now)?;
+                return Ok(None);
+            }
+        };
+        let recurrence = Recurrence::parse(&series.recurrence)?;
+        let today = now.date().naive_local();
+        let mut due_date = recurrence.next(task.due_date.unwrap_or(today));
+        while let Some(date) = due_date {
+            if date > today {
+                break;
+            }
+            due_date = recurrence.next(date);
+        }
+
+        let mut next = Task::new(task.parent_id, &task.title, &series.body, now);
+        next.priority = task.priority;
+        next.due_date = due_date;
+        next.series_id = Some(series.id);
+        next.tags = task.tags;
+        store.finish_recurring_task(id, now, &next).map(Some)
+    }
+
+    /// Opens a finished task again and forgets when it was finished.
+    pub fn reopen(store: &dyn Store, id: i32) -> Result<()> {
+        store.reopen_task(id)
+    }
+
+    pub fn all(store: &dyn Store) -> Result<Vec<Task>> {
+        store.all_tasks()
+    }
+
+    pub fn find(store: &dyn Store, id: i32) -> Result<Task> {
+        store.find_task(id)?.ok_or_else(|| Error::not_found("task", id))
+    }
+
+    pub fn find_recently_updated(store: &dyn Store, days: i32) -> Result<Vec<Review>> {
+        store.find_recently_updated(days)
+    }
+
+    pub fn find_aux(store: &dyn Store, id: i32) -> Result<TaskAux> {
+        store.find_task_aux(id)?.ok_or_else(|| Error::not_found("task", id))
+    }
+
+    pub fn all_aux(store: &dyn Store) -> Result<Vec<TaskAux>> {
+        store.all_tasks_aux()
+    }
+
+    /// The task `root` and its descendants, or every root task and theirs, down to `depth` levels
+    /// below the roots if given. Listed depth-first, with siblings in ID order.
+    pub fn subtree(store: &dyn Store,
+                   root: Option<i32>,
+                   depth: Option<i32>)
+                   -> Result<Vec<TreeNode>> {
+        if depth.is_some_and(|depth| depth < 0) {
+            return Err(Error::Validation("A subtree's depth cannot be negative".to_owned()));
         }
-        result
-    }
-
-    pub fn notes(self, conn: &Connection) -> Vec<Note> {
-        Self::find_notes(conn, self.id)
-    }
-
-    pub fn open_leaves(conn: &Connection) -> Vec<Task> {
-        let mut result = vec![];
-        for row in &conn.query("
-SELECT t1.id, t1.parent_id, t1.title, t1.body, t1.open, t1.date_created
-FROM task t1
-WHERE NOT EXISTS (
-    SELECT t2.id FROM task t2 WHERE t1.id = t2.parent_id AND t2.open = TRUE
-    ) AND t1.open = TRUE
-ORDER BY date_created DESC",
-                               &[])
-                        .unwrap() {
-            let r = &mut result;
-            r.push(Task::unpack(row));
+        if let Some(root) = root {
+            Task::find(store, root)?;
         }
-        result
+        store.subtree(root, depth)
+    }
+
+    /// `Task::subtree` with the roots and the children of each task sorted by `order`.
+    pub fn sorted_subtree(store: &dyn Store,
+                          root: Option<i32>,
+                          depth: Option<i32>,
+                          order: TreeOrder)
+                          -> Result<Vec<TreeNode>> {
+        if order != TreeOrder::Activity {
+            let nodes = Task::subtree(store, root, depth)?;
+            return Ok(sort_siblings(nodes, |a, b| compare_nodes(order, &HashMap::new(), a, b)));
+        }
+
+        // activity below the requested depth still counts, so load the whole subtree
+        let nodes = Task::subtree(store, root, None)?;
+        let notes: HashMap<i32, DateTime<Local>> = store.latest_note_dates()?
+            .into_iter()
+            .collect();
+        let mut activity: HashMap<i32, DateTime<Local>> = HashMap::new();
+        for node in &nodes {
+            let task = &node.task;
+            let latest = task.date_finished
+                .into_iter()
+                .chain(notes.get(&task.id).cloned())
+                .fold(task.date_created, std::cmp::max);
+            for id in &node.path {
+                let date = activity.entry(*id).or_insert(latest);
+                if latest > *date {
+                    *date = latest;
+                }
+            }
+        }
+        let mut nodes = sort_siblings(nodes, |a, b| compare_nodes(order, &activity, a, b));
+        if let Some(depth) = depth {
+            nodes.retain(|node| node.depth <= depth);
+        }
+        Ok(nodes)
+    }
+
+    /// Notes started within `[from, to)`, grouped by task in ascending task ID order.
+    pub fn report(store: &dyn Store,
+                  from: DateTime<Local>,
+                  to: DateTime<Local>)
+                  -> Result<Vec<TaskReport>> {
+        let mut result: Vec<TaskReport> = vec![];
+        for note in store.find_notes_between(from, to)? {
+            if result.last().is_none_or(|report| report.task.id != note.task_id) {
+                result.push(TaskReport {
+                                task: Task::find(store, note.task_id)?,
+                                notes: vec![],
+                                duration_seconds: 0f32,
+                            });
+            }
+            let report = result.last_mut().unwrap();
+            report.duration_seconds += note.duration_seconds;
+            report.notes.push(note);
+        }
+        Ok(result)
+    }
+
+    /// Tasks and notes matching `query`, most relevant first.
+    pub fn search(store: &dyn Store,
+                  query: &str,
+                  filter: &SearchFilter)
+                  -> Result<Vec<SearchResult>> {
+        if query.trim().is_empty() {
+            return Err(Error::Validation("Nothing to search for".to_owned()));
+        }
+        store.search(query, filter)
+    }
+
+    pub fn find_notes(store: &dyn Store, id: i32) -> Result<Vec<Note>> {
+        store.find_notes(id)
+    }
+
+    pub fn find_notes_aux(store: &dyn Store, id: i32) -> Result<Vec<NoteAux>> {
+        store.find_notes_aux(id)
+    }
+
+    pub fn notes(self, store: &dyn Store) -> Result<Vec<Note>> {
+        Self::find_notes(store, self.id)
+    }
+
+    pub fn open_leaves(store: &dyn Store, order: TaskOrder) -> Result<Vec<Task>> {
+        store.open_leaves(order)
     }
 }
 
+#[test]
+fn test_set_parent() {
+    use store::memory::sample_store;
+
+    let store = sample_store();
+    Task::set_parent(&store, 2, Some(4)).unwrap();
+    assert_eq!(Task::find(&store, 2).unwrap().parent_id, Some(4));
+    Task::set_parent(&store, 4, None).unwrap();
+
+    match Task::set_parent(&store, 4, Some(3)) {
+        Err(Error::Validation(ref reason)) => assert!(reason.ends_with("(3 -> 2 -> 4)")),
+        other => panic!("unexpected {:?}", other),
+    }
+    assert!(Task::set_parent(&store, 1, Some(1)).is_err());
+    assert!(Task::set_parent(&store, 1, Some(42)).is_err());
+    assert_eq!(Task::find(&store, 4).unwrap().parent_id, None);
+}
+
+#[test]
+fn test_dependencies() {
+    use store::memory::sample_store;
+
+    let store = sample_store();
+    Task::block(&store, 4, 3).unwrap();
+    Task::block(&store, 1, 4).unwrap();
+    Task::block(&store, 1, 4).unwrap();
+
+    match Task::block(&store, 3, 1) {
+        Err(Error::Validation(ref reason)) => assert!(reason.ends_with("(1 -> 4 -> 3)")),
+        other => panic!("unexpected {:?}", other),
+    }
+    assert!(Task::block(&store, 3, 3).is_err());
+    assert!(Task::block(&store, 3, 42).is_err());
+
+    let blocked: Vec<(i32, Vec<i32>)> = Task::open_blockers(&store).unwrap().into_iter().collect();
+    assert_eq!(blocked, vec![(1, vec![4]), (4, vec![3])]);
+    Task::finish(&store, 3).unwrap();
+    assert_eq!(Task::open_blockers(&store).unwrap().keys().collect::<Vec<_>>(), vec![&1]);
+
+    Task::unblock(&store, 1, 4).unwrap();
+    assert!(Task::unblock(&store, 1, 4).is_err());
+    Task::delete(&store, 3).unwrap();
+    assert!(store.all_dependencies().unwrap().is_empty());
+}
+
+#[test]
+fn test_recurring_tasks() {
+    use store::memory::sample_store;
+
+    let store = sample_store();
+    Task::update(&store, 2, "Weekly report", "Template").unwrap();
+    Task::tag(&store, 2, &["work"]).unwrap();
+    let series = Task::repeat(&store, 2, "weekly").unwrap();
+    let now = Local::now();
+    Note::create(&store, 2, "Wrote it", "Filled in", now, now + Duration::minutes(5)).unwrap();
+    assert!(Task::repeat(&store, 2, "fortnightly").is_err());
+
+    let today = Local::today().naive_local();
+    let next = Task::finish(&store, 2).unwrap().unwrap();
+    assert_eq!((next.id, next.parent_id, next.series_id), (5, Some(1), Some(series.id)));
+    assert_eq!((next.title.as_str(), next.body.as_str()), ("Weekly report", "Template"));
+    assert_eq!(next.tags, vec!["work"]);
+    assert_eq!(next.due_date, Some(today + Duration::days(7)));
+    assert!(Task::finish(&store, 2).unwrap().is_none());
+
+    let next = Task::finish(&store, 5).unwrap().unwrap();
+    assert_eq!(next.due_date, Some(today + Duration::days(14)));
+    Task::stop_repeating(&store, 6).unwrap();
+    assert!(Task::finish(&store, 6).unwrap().is_none());
+    assert_eq!(Task::find(&store, 5).unwrap().series_id, Some(series.id));
+}
+
+#[test]
+fn test_sorted_subtree() {
+    use store::memory::sample_store;
+
+    let store = sample_store();
+    let mut task = Task::new(Some(1), "Second child", "", Local::now() + Duration::seconds(4));
+    task.priority = Some(2);
+    task.create(&store).unwrap();
+    let later = Local::now() + Duration::hours(1);
+    Note::create(&store, 3, "note", "body", later, later + Duration::minutes(5)).unwrap();
+    let ids = |depth, order| -> Vec<i32> {
+        Task::sorted_subtree(&store, None, depth, order)
+            .unwrap()
+            .iter()
+            .map(|node| node.task.id)
+            .collect()
+    };
+    assert_eq!(ids(None, TreeOrder::Id), vec![1, 2, 3, 5, 4]);
+    assert_eq!(ids(None, TreeOrder::Created), vec![4, 1, 5, 2, 3]);
+    assert_eq!(ids(None, TreeOrder::Title), vec![4, 1, 2, 3, 5]);
+    assert_eq!(ids(None, TreeOrder::Priority), vec![1, 5, 2, 3, 4]);
+    assert_eq!(ids(None, TreeOrder::Activity), vec![1, 2, 3, 5, 4]);
+    assert_eq!(ids(Some(0), TreeOrder::Activity), vec![1, 4]);
+    assert!(TreeOrder::named("size").is_err());
+}
+
+fn compare_nodes(order: TreeOrder,
+                 activity: &HashMap<i32, DateTime<Local>>,
+                 a: &TreeNode,
+                 b: &TreeNode)
+                 -> Ordering {
+    let (a, b) = (&a.task, &b.task);
+    let ordering = match order {
+        TreeOrder::Id => Ordering::Equal,
+        TreeOrder::Created => b.date_created.cmp(&a.date_created),
+        TreeOrder::Title => a.title.to_lowercase().cmp(&b.title.to_lowercase()),
+        TreeOrder::Priority => cmp_some_first(&a.priority, &b.priority),
+        TreeOrder::Activity => activity.get(&b.id).cmp(&activity.get(&a.id)),
+    };
+    ordering.then(a.id.cmp(&b.id))
+}
+
+fn validate_tag(tag: &str) -> Result<()> {
+    if tag.is_empty() || tag.starts_with('+') || tag.starts_with('-') ||
+       tag.contains(char::is_whitespace) {
+        return Err(Error::Validation(format!("{:?} is not a valid tag", tag)));
+    }
+    Ok(())
+}
+
 impl std::fmt::Display for Task {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{:3}: {}", self.id, self.title)
+        write!(f, "{:3}: {}", self.id, self.title)?;
+        for tag in &self.tags {
+            write!(f, " #{}", tag)?;
+        }
+        match (self.priority, self.due_date) {
+            (Some(priority), Some(due)) => write!(f, " [p{}, due {}]", priority, due),
+            (Some(priority), None) => write!(f, " [p{}]", priority),
+            (None, Some(due)) => write!(f, " [due {}]", due),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+fn check_note_dates(date_start: DateTime<Local>, date_end: DateTime<Local>) -> Result<()> {
+    if date_end <= date_start {
+        return Err(Error::Validation("A note must end after it starts".to_owned()));
+    }
+    Ok(())
+}
+
+impl Timer {
+    pub fn start(store: &dyn Store, task_id: i32) -> Result<Timer> {
+        Task::find(store, task_id)?;
+        if let Some(timer) = store.active_timer()? {
+            return Err(Error::Validation(format!("A timer is already running for task {}",
+                                                 timer.task_id)));
+        }
+        store.start_timer(task_id, Local::now())
+    }
+
+    pub fn active(store: &dyn Store) -> Result<Option<Timer>> {
+        store.active_timer()
+    }
+
+    /// Stops the running timer, recording the time since it started as a note.
+    pub fn stop(store: &dyn Store,
+                body: &str,
+                task_body: &str,
+                date_end: DateTime<Local>)
+                -> Result<Note> {
+        let timer = store.active_timer()?
+            .ok_or_else(|| Error::Validation("No timer is running".to_owned()))?;
+        check_note_dates(timer.date_start, date_end)?;
+        store.stop_timer(body, task_body, date_end)
     }
 }
 
 impl Note {
-    pub fn create_table(conn: &Connection) {
-        conn.execute("
-CREATE TABLE IF NOT EXISTS note (
-    id          SERIAL PRIMARY KEY,
-    task_id     INTEGER REFERENCES task(id) ON DELETE CASCADE,
-    body        TEXT NOT NULL,
-    date_start  TIMESTAMP WITH TIME ZONE NOT NULL,
-    date_end    TIMESTAMP WITH TIME ZONE NOT NULL
-);
-    ",
-                     &[])
-            .unwrap();
-    }
-
-    pub fn drop_table(conn: &Connection) {
-        conn.execute("DROP TABLE IF EXISTS note CASCADE", &[])
-            .unwrap();
-    }
-
-    pub fn create(conn: &Connection,
+    pub fn create(store: &dyn Store,
                   task_id: i32,
                   body: &str,
                   task_body: &str,
                   date_start: DateTime<Local>,
-                  date_end: DateTime<Local>) {
-        let trans = conn.transaction().unwrap();
-
-        trans
-            .execute("INSERT INTO note(task_id, body, date_start, date_end) VALUES ($1, $2, $3, \
-                      $4)",
-                     &[&task_id, &body, &date_start, &date_end])
-            .unwrap();
-        trans
-            .execute("UPDATE task SET body = $2 WHERE id = $1",
-                     &[&task_id, &task_body])
-            .unwrap();
-        trans.commit().unwrap();
-    }
-
-    pub fn delete(conn: &Connection, id: i32) {
-        conn.execute("DELETE FROM note WHERE id = $1", &[&id])
-            .unwrap();
+                  date_end: DateTime<Local>)
+                  -> Result<Note> {
+        check_note_dates(date_start, date_end)?;
+        store.create_note(task_id, body, task_body, date_start, date_end)
+    }
+
+    pub fn find(store: &dyn Store, id: i32) -> Result<Note> {
+        store.find_note(id)?.ok_or_else(|| Error::not_found("note", id))
+    }
+
+    /// Replaces the note's body, and its task's body, in one transaction.
+    pub fn update(store: &dyn Store, id: i32, body: &str, task_body: &str) -> Result<()> {
+        store.update_note(id, body, task_body)
+    }
+
+    pub fn delete(store: &dyn Store, id: i32) -> Result<()> {
+        store.delete_note(id)
     }
 }
@@ -0,0 +1,229 @@
+use chrono::*;
+
+use error::{Error, Result};
+
+/// The period names accepted by `Period::named`
+pub const PERIOD_NAMES: &[&str] = &["today",
+                                    "yesterday",
+                                    "this-week",
+                                    "last-week",
+                                    "this-month",
+                                    "last-month",
+                                    "this-quarter",
+                                    "last-quarter",
+                                    "this-year",
+                                    "last-year"];
+
+/// A range of whole days, from the start of `from` up to (but not including) the start of `to`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Period {
+    pub from: Date<Local>,
+    pub to: Date<Local>,
+}
+
+/// The first day of the month `months` months after `year`-`month`.
+fn first_of_month(year: i32, month: u32, months: i32) -> Date<Local> {
+    let index = year * 12 + month as i32 - 1 + months;
+    Local.ymd(index / 12, (index % 12) as u32 + 1, 1)
+}
+
+fn parse_date(text: &str) -> Result<Date<Local>> {
+    let date = NaiveDate::parse_from_str(text, "%Y-%m-%d")
+        .map_err(|_| Error::Validation(format!("Expected a YYYY-MM-DD date, got {:?}", text)))?;
+    Ok(Local.ymd(date.year(), date.month(), date.day()))
+}
+
+/// Accepts `today`, `yesterday`, `tomorrow` or a YYYY-MM-DD date.
+pub fn parse_day(text: &str, today: Date<Local>) -> Result<Date<Local>> {
+    match text {
+        "today" => Ok(today),
+        "yesterday" => Ok(today.pred()),
+        "tomorrow" => Ok(today.succ()),
+        _ => parse_date(text),
+    }
+}
+
+/// Accepts hours and minutes such as `1h30m`, `2h` or `45m`.
+pub fn parse_duration(text: &str) -> Result<Duration> {
+    let invalid = || Error::Validation(format!("Expected a duration like 1h30m, got {:?}", text));
+    let mut minutes = 0i64;
+    let mut number = String::new();
+    for c in text.chars() {
+        match c {
+            '0'..='9' => number.push(c),
+            'h' | 'm' if !number.is_empty() => {
+                let value = number.parse::<i64>().map_err(|_| invalid())?;
+                minutes += if c == 'h' { value * 60 } else { value };
+                number.clear();
+            }
+            _ => return Err(invalid()),
+        }
+    }
+    if !number.is_empty() || minutes == 0 {
+        return Err(invalid());
+    }
+    Ok(Duration::minutes(minutes))
+}
+#[test]
+fn test_parse_duration() {
+    assert_eq!(parse_duration("1h30m").unwrap(), Duration::minutes(90));
+    assert_eq!(parse_duration("45m").unwrap(), Duration::minutes(45));
+    assert_eq!(parse_duration("2h").unwrap(), Duration::hours(2));
+    assert!(parse_duration("90").is_err());
+    assert!(parse_duration("h").is_err());
+    assert!(parse_duration("0m").is_err());
+}
+
+/// `time` on `day` in the local time zone, failing for a time skipped or repeated by a daylight
+/// saving change.
+fn local_time(day: NaiveDate, time: NaiveTime) -> Result<DateTime<Local>> {
+    let local = day.and_time(time);
+    match Local.from_local_datetime(&local) {
+        LocalResult::Single(result) => Ok(result),
+        LocalResult::None => {
+            Err(Error::Validation(format!("{} does not exist in the local time zone", local)))
+        }
+        LocalResult::Ambiguous(..) => {
+            Err(Error::Validation(format!("{} is ambiguous in the local time zone", local)))
+        }
+    }
+}
+
+/// Accepts an HH:MM time on `day`.
+pub fn parse_time(text: &str, day: Date<Local>) -> Result<DateTime<Local>> {
+    let time = NaiveTime::parse_from_str(text, "%H:%M")
+        .map_err(|_| Error::Validation(format!("Expected an HH:MM time, got {:?}", text)))?;
+    local_time(day.naive_local(), time)
+}
+#[test]
+fn test_parse_time() {
+    let day = Local.ymd(2017, 2, 15);
+    assert_eq!(parse_time("09:30", day).unwrap(), day.and_hms(9, 30, 0));
+    assert!(parse_time("9.30", day).is_err());
+    assert!(parse_time("24:00", day).is_err());
+}
+
+/// Works out when a backfilled note started and ended from any two of `start`, `end` and
+/// `duration`. A lone `start` or `duration` on today's date runs until `now`.
+pub fn time_range(start: Option<DateTime<Local>>,
+                  end: Option<DateTime<Local>>,
+                  duration: Option<Duration>,
+                  now: DateTime<Local>)
+                  -> Result<(DateTime<Local>, DateTime<Local>)> {
+    let (start, end) = match (start, end, duration) {
+        (Some(start), Some(end), None) => (start, end),
+        (Some(start), None, Some(duration)) => (start, start + duration),
+        (None, Some(end), Some(duration)) => (end - duration, end),
+        (Some(start), None, None) if start.date() == now.date() => (start, now),
+        (None, None, Some(duration)) => (now - duration, now),
+        (Some(_), Some(_), Some(_)) => {
+            return Err(Error::Validation("Give at most two of a start, an end and a duration"
+                .to_owned()))
+        }
+        _ => {
+            return Err(Error::Validation("Give a start or a duration, and an end unless the \
+                                          note runs until now"
+                .to_owned()))
+        }
+    };
+    if end <= start {
+        return Err(Error::Validation("A note has to end after it starts".to_owned()));
+    }
+    Ok((start, end))
+}
+#[test]
+fn test_time_range() {
+    let now = Local.ymd(2017, 2, 15).and_hms(18, 0, 0);
+    let at = |h| Local.ymd(2017, 2, 15).and_hms(h, 0, 0);
+    let hour = Some(Duration::hours(1));
+
+    assert_eq!(time_range(Some(at(9)), Some(at(11)), None, now).unwrap(), (at(9), at(11)));
+    assert_eq!(time_range(Some(at(9)), None, hour, now).unwrap(), (at(9), at(10)));
+    assert_eq!(time_range(None, Some(at(11)), hour, now).unwrap(), (at(10), at(11)));
+    assert_eq!(time_range(Some(at(9)), None, None, now).unwrap(), (at(9), now));
+    assert_eq!(time_range(None, None, hour, now).unwrap(), (at(17), now));
+    assert!(time_range(Some(at(11)), Some(at(9)), None, now).is_err());
+    assert!(time_range(Some(at(9)), Some(at(9)), None, now).is_err());
+    assert!(time_range(None, Some(at(9)), None, now).is_err());
+    assert!(time_range(Some(at(9) - Duration::days(1)), None, None, now).is_err());
+}
+
+impl Period {
+    /// Weeks start on Monday, quarters in January, April, July and October.
+    pub fn named(name: &str, today: Date<Local>) -> Result<Period> {
+        let monday = today - Duration::days(today.weekday().num_days_from_monday() as i64);
+        let month = first_of_month(today.year(), today.month(), 0);
+        let quarter = first_of_month(today.year(), (today.month() - 1) / 3 * 3 + 1, 0);
+        let year = Local.ymd(today.year(), 1, 1);
+
+        let (from, to) = match name {
+            "today" => (today, today.succ()),
+            "yesterday" => (today.pred(), today),
+            "this-week" => (monday, monday + Duration::days(7)),
+            "last-week" => (monday - Duration::days(7), monday),
+            "this-month" => (month, first_of_month(month.year(), month.month(), 1)),
+            "last-month" => (first_of_month(month.year(), month.month(), -1), month),
+            "this-quarter" => (quarter, first_of_month(quarter.year(), quarter.month(), 3)),
+            "last-quarter" => (first_of_month(quarter.year(), quarter.month(), -3), quarter),
+            "this-year" => (year, Local.ymd(today.year() + 1, 1, 1)),
+            "last-year" => (Local.ymd(today.year() - 1, 1, 1), year),
+            _ => {
+                return Err(Error::Validation(format!("Unknown period {:?}, expected one of {}",
+                                                     name,
+                                                     PERIOD_NAMES.join(", "))))
+            }
+        };
+        Ok(Period { from, to })
+    }
+
+    /// Both ends are inclusive YYYY-MM-DD dates; a missing `to` means today.
+    pub fn between(from: &str, to: Option<&str>, today: Date<Local>) -> Result<Period> {
+        let from = parse_date(from)?;
+        let to = match to {
+            Some(to) => parse_date(to)?,
+            None => today,
+        };
+        if to < from {
+            return Err(Error::Validation("A period cannot end before it starts".to_owned()));
+        }
+        Ok(Period {
+               from,
+               to: to.succ(),
+           })
+    }
+
+    pub fn start(&self) -> Result<DateTime<Local>> {
+        local_time(self.from.naive_local(), NaiveTime::from_hms(0, 0, 0))
+    }
+
+    pub fn end(&self) -> Result<DateTime<Local>> {
+        local_time(self.to.naive_local(), NaiveTime::from_hms(0, 0, 0))
+    }
+}
+#[test]
+fn test_named_periods() {
+    // a Wednesday
+    let today = Local.ymd(2017, 2, 15);
+    let period = |name| {
+        let period = Period::named(name, today).unwrap();
+        (period.from.naive_local(), period.to.naive_local())
+    };
+    let day = |y, m, d| NaiveDate::from_ymd(y, m, d);
+
+    assert_eq!(period("yesterday"), (day(2017, 2, 14), day(2017, 2, 15)));
+    assert_eq!(period("last-week"), (day(2017, 2, 6), day(2017, 2, 13)));
+    assert_eq!(period("this-month"), (day(2017, 2, 1), day(2017, 3, 1)));
+    assert_eq!(period("last-quarter"), (day(2016, 10, 1), day(2017, 1, 1)));
+    assert_eq!(period("last-year"), (day(2016, 1, 1), day(2017, 1, 1)));
+    assert!(Period::named("last-century", today).is_err());
+}
+#[test]
+fn test_between() {
+    let today = Local.ymd(2017, 2, 15);
+    let period = Period::between("2017-01-31", Some("2017-02-01"), today).unwrap();
+    assert_eq!(period.to, Local.ymd(2017, 2, 2));
+    assert_eq!(period.end().unwrap(), Local.ymd(2017, 2, 2).and_hms(0, 0, 0));
+    assert_eq!(Period::between("2017-02-01", None, today).unwrap().to, today.succ());
+    assert!(Period::between("2017-02-01", Some("2017-01-01"), today).is_err());
+    assert!(Period::between("yesterday", None, today).is_err());
+}
@@ -0,0 +1,190 @@
+use chrono::*;
+
+use error::{Error, Result};
+
+/// How far `Recurrence::next` looks for a day matching cron fields; enough for a 29th of
+/// February across a skipped leap year.
+const CRON_SEARCH_DAYS: i64 = 8 * 366;
+/// The largest N in `every N days`, weeks, months or years
+const MAX_COUNT: i32 = 1000;
+
+const MONTH_NAMES: &[&str] = &["jan", "feb", "mar", "apr", "may", "jun", "jul",
+                               "aug", "sep", "oct", "nov", "dec"];
+const WEEKDAY_NAMES: &[&str] = &["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// How often a recurring task comes back. Written as one of:
+///
+/// - `daily`, `weekly`, `monthly` or `yearly`
+/// - `every N days`, `every N weeks`, `every N months` or `every N years`
+/// - the day fields of a cron expression, `<day of month> <month> <day of week>`, such as
+///   `* * mon-fri` or `1,15 * *`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Recurrence {
+    Days(i64),
+    /// Landing on the last day of shorter months, so the 31st is followed by the 28th or 30th
+    Months(i32),
+    /// Indexed by day of month (1-31), month (1-12) and day of week (0-6 from Sunday)
+    Cron {
+        days: Vec<bool>,
+        months: Vec<bool>,
+        weekdays: Vec<bool>,
+    },
+}
+
+fn invalid(text: &str) -> Error {
+    Error::Validation(format!("Expected daily, weekly, monthly, yearly, every N days, weeks, \
+                               months or years, or cron day fields such as \"* * mon-fri\", \
+                               got {:?}",
+                              text))
+}
+
+/// One value of a cron field, as a number or a name
+fn parse_value(text: &str, names: &[&str], min: usize, max: usize) -> Option<usize> {
+    let value = match names.iter().position(|name| *name == text) {
+        Some(i) => i + min,
+        None => text.parse().ok()?,
+    };
+    if value >= min && value <= max {
+        Some(value)
+    } else {
+        None
+    }
+}
+
+/// A comma-separated list of values and ranges, or `*`, as flags indexed by value.
+fn parse_field(text: &str, names: &[&str], min: usize, max: usize) -> Option<Vec<bool>> {
+    let mut flags = vec![false; max + 1];
+    if text == "*" {
+        for flag in &mut flags[min..] {
+            *flag = true;
+        }
+        return Some(flags);
+    }
+    for item in text.split(',') {
+        let mut bounds = item.splitn(2, '-');
+        let from = parse_value(bounds.next()?, names, min, max)?;
+        let to = match bounds.next() {
+            Some(to) => parse_value(to, names, min, max)?,
+            None => from,
+        };
+        if to < from {
+            return None;
+        }
+        for flag in &mut flags[from..to + 1] {
+            *flag = true;
+        }
+    }
+    Some(flags)
+}
+
+/// The same day `months` months later, or the last day of that month if it is shorter; `None`
+/// past the last date chrono can represent.
+fn add_months(date: NaiveDate, months: i32) -> Option<NaiveDate> {
+    let index = (date.year() * 12 + date.month0() as i32).checked_add(months)?;
+    let (year, month) = (index / 12, (index % 12) as u32 + 1);
+    (1..date.day() + 1).rev().filter_map(|day| NaiveDate::from_ymd_opt(year, month, day)).next()
+}
+
+impl Recurrence {
+    pub fn parse(text: &str) -> Result<Recurrence> {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        let recurrence = match words[..] {
+            ["daily"] => Recurrence::Days(1),
+            ["weekly"] => Recurrence::Days(7),
+            ["monthly"] => Recurrence::Months(1),
+            ["yearly"] => Recurrence::Months(12),
+            ["every", count, unit] => {
+                let count: i32 = count.parse().map_err(|_| invalid(text))?;
+                if count < 1 {
+                    return Err(invalid(text));
+                }
+                if count > MAX_COUNT {
+                    return Err(Error::Validation(format!("Expected at most {} in {:?}",
+                                                         MAX_COUNT,
+                                                         text)));
+                }
+                match unit.trim_end_matches('s') {
+                    "day" => Recurrence::Days(count as i64),
+                    "week" => Recurrence::Days(count as i64 * 7),
+                    "month" => Recurrence::Months(count),
+                    "year" => Recurrence::Months(count * 12),
+                    _ => return Err(invalid(text)),
+                }
+            }
+            [days, months, weekdays] => {
+                let lower = weekdays.to_lowercase();
+                let mut weekdays = parse_field(&lower, WEEKDAY_NAMES, 0, 7)
+                    .ok_or_else(|| invalid(text))?;
+                // cron also accepts 7 for Sunday
+                if weekdays.pop() == Some(true) {
+                    weekdays[0] = true;
+                }
+                Recurrence::Cron {
+                    days: parse_field(days, &[], 1, 31).ok_or_else(|| invalid(text))?,
+                    months: parse_field(&months.to_lowercase(), MONTH_NAMES, 1, 12)
+                        .ok_or_else(|| invalid(text))?,
+                    weekdays,
+                }
+            }
+            _ => return Err(invalid(text)),
+        };
+        if recurrence.next(NaiveDate::from_ymd(2000, 1, 1)).is_none() {
+            return Err(Error::Validation(format!("{:?} never matches a day", text)));
+        }
+        Ok(recurrence)
+    }
+
+    /// The first day of the series after `date`, `None` if cron fields match no day or the day
+    /// is past the last date chrono can represent.
+    pub fn next(&self, date: NaiveDate) -> Option<NaiveDate> {
+        match *self {
+            Recurrence::Days(days) => date.checked_add(Duration::days(days)),
+            Recurrence::Months(months) => add_months(date, months),
+            Recurrence::Cron { ref days, ref months, ref weekdays } => {
+                // as in cron, restricting both kinds of days matches either of them
+                let any_day = days.iter().skip(1).all(|&flag| flag);
+                let any_weekday = weekdays.iter().all(|&flag| flag);
+                (1..CRON_SEARCH_DAYS + 1)
+                    .map_while(|i| date.checked_add(Duration::days(i)))
+                    .find(|day| {
+                        let by_day = days[day.day() as usize];
+                        let by_weekday = weekdays[day.weekday().num_days_from_sunday() as usize];
+                        let by_either = if any_day || any_weekday {
+                            by_day && by_weekday
+                        } else {
+                            by_day || by_weekday
+                        };
+                        months[day.month() as usize] && by_either
+                    })
+            }
+        }
+    }
+}
+#[test]
+fn test_recurrence() {
+    let day = |y, m, d| NaiveDate::from_ymd(y, m, d);
+    let next = |rule: &str, date| Recurrence::parse(rule).unwrap().next(date).unwrap();
+
+    assert_eq!(next("daily", day(2017, 2, 28)), day(2017, 3, 1));
+    assert_eq!(next("every 2 weeks", day(2017, 2, 15)), day(2017, 3, 1));
+    assert_eq!(next("monthly", day(2017, 1, 31)), day(2017, 2, 28));
+    assert_eq!(next("every 1 year", day(2016, 2, 29)), day(2017, 2, 28));
+    // 2017-02-17 is a Friday
+    assert_eq!(next("* * mon-fri", day(2017, 2, 17)), day(2017, 2, 20));
+    assert_eq!(next("1,15 * *", day(2017, 2, 15)), day(2017, 3, 1));
+    assert_eq!(next("29 feb *", day(2017, 1, 1)), day(2020, 2, 29));
+    assert_eq!(next("13 * fri", day(2017, 2, 15)), day(2017, 2, 17));
+    assert_eq!(next("* * 7", day(2017, 2, 15)), day(2017, 2, 19));
+
+    assert!(Recurrence::parse("fortnightly").is_err());
+    assert!(Recurrence::parse("every 0 days").is_err());
+    assert!(Recurrence::parse("32 * *").is_err());
+    assert!(Recurrence::parse("31 feb *").is_err());
+    assert!(Recurrence::parse("every 1001 days").is_err());
+    assert!(Recurrence::parse("every 2147483647 years").is_err());
+    assert!(Recurrence::parse("every 99999999999 weeks").is_err());
+    let last = NaiveDate::from_ymd(262143, 12, 31);
+    assert_eq!(Recurrence::parse("every 1000 years").unwrap().next(last), None);
+    assert_eq!(Recurrence::parse("every 1000 weeks").unwrap().next(last), None);
+    assert_eq!(Recurrence::parse("* * *").unwrap().next(last), None);
+}
@@ -0,0 +1,210 @@
+use std::env;
+use std::io;
+
+use termion;
+use termion::{color, style};
+
+use models::Task;
+
+/// The width assumed when stdout is not a terminal and `$COLUMNS` is unset
+pub const DEFAULT_WIDTH: usize = 80;
+/// The narrowest text `Renderer::wrap` leaves after indenting, however deep
+const MIN_WRAP_WIDTH: usize = 20;
+
+/// Formats text output for a terminal: colour, tree guides, aligned columns and wrapping.
+#[derive(Debug, Clone, Copy)]
+pub struct Renderer {
+    /// Whether to print ANSI colours and styles
+    pub colour: bool,
+    /// Columns available for wrapping
+    pub width: usize,
+}
+
+impl Renderer {
+    /// Colours only when stdout is a terminal and `$NO_COLOR` is unset; wraps at the terminal's
+    /// width, falling back to `$COLUMNS`, then to 80 columns.
+    pub fn stdout() -> Renderer {
+        let tty = termion::is_tty(&io::stdout());
+        let width = match termion::terminal_size() {
+            Ok((width, _)) if tty && width > 0 => width as usize,
+            _ => {
+                env::var("COLUMNS")
+                    .ok()
+                    .and_then(|columns| columns.parse().ok())
+                    .unwrap_or(DEFAULT_WIDTH)
+            }
+        };
+        Renderer {
+            colour: tty && env::var_os("NO_COLOR").is_none(),
+            width,
+        }
+    }
+
+    /// No colour, wrapping at `width` columns
+    pub fn plain(width: usize) -> Renderer {
+        Renderer {
+            colour: false,
+            width,
+        }
+    }
+
+    fn paint<C: color::Color>(&self, text: &str, colour: C) -> String {
+        if self.colour && !text.is_empty() {
+            format!("{}{}{}", color::Fg(colour), text, color::Fg(color::Reset))
+        } else {
+            text.to_owned()
+        }
+    }
+
+    pub fn bold(&self, text: &str) -> String {
+        if self.colour && !text.is_empty() {
+            format!("{}{}{}", style::Bold, text, style::Reset)
+        } else {
+            text.to_owned()
+        }
+    }
+
+    pub fn dim(&self, text: &str) -> String {
+        if self.colour && !text.is_empty() {
+            format!("{}{}{}", style::Faint, text, style::Reset)
+        } else {
+            text.to_owned()
+        }
+    }
+
+    /// `OPEN` in yellow or `DONE` in green
+    pub fn status(&self, open: bool) -> String {
+        if open {
+            self.paint("OPEN", color::Yellow)
+        } else {
+            self.paint("DONE", color::Green)
+        }
+    }
+
+    /// A task's ID right-aligned to `id_width`, then its title, tags, priority and due date, as
+    /// in its `Display` form. Finished tasks are green.
+    pub fn task(&self, task: &Task, id_width: usize) -> String {
+        let id = format!("{:>1$}", task.id, id_width);
+        format!("{}  {}", self.dim(&id), self.task_label(task))
+    }
+
+    /// A task's title, tags, priority and due date, without its ID
+    pub fn task_label(&self, task: &Task) -> String {
+        let mut result = if task.open {
+            task.title.clone()
+        } else {
+            self.paint(&task.title, color::Green)
+        };
+        for tag in &task.tags {
+            result.push_str(&self.paint(&format!(" #{}", tag), color::Cyan));
+        }
+        let meta = match (task.priority, task.due_date) {
+            (Some(priority), Some(due)) => format!(" [p{}, due {}]", priority, due),
+            (Some(priority), None) => format!(" [p{}]", priority),
+            (None, Some(due)) => format!(" [due {}]", due),
+            (None, None) => String::new(),
+        };
+        result.push_str(&self.paint(&meta, color::Magenta));
+        result
+    }
+
+    /// `text` indented by `indent` spaces, with lines too long for the width wrapped at word
+    /// boundaries. Wrapped lines keep their leading whitespace; longer words are left whole.
+    pub fn wrap(&self, text: &str, indent: usize) -> String {
+        let width = if self.width > indent + MIN_WRAP_WIDTH {
+            self.width - indent
+        } else {
+            MIN_WRAP_WIDTH
+        };
+        let prefix = " ".repeat(indent);
+        let mut lines = vec![];
+        for line in text.lines() {
+            if line.trim().is_empty() {
+                lines.push(String::new());
+                continue;
+            }
+            if line.chars().count() <= width {
+                lines.push(format!("{}{}", prefix, line));
+                continue;
+            }
+            let words = line.trim_start();
+            let lead = &line[..line.len() - words.len()];
+            let mut current = String::new();
+            let mut current_width = 0;
+            for word in words.split_whitespace() {
+                let word_width = word.chars().count();
+                if current_width > 0 && current_width + 1 + word_width > width {
+                    lines.push(format!("{}{}", prefix, current));
+                    current_width = 0;
+                }
+                if current_width == 0 {
+                    current = lead.to_owned();
+                    current_width = lead.chars().count();
+                } else {
+                    current.push(' ');
+                    current_width += 1;
+                }
+                current.push_str(word);
+                current_width += word_width;
+            }
+            lines.push(format!("{}{}", prefix, current));
+        }
+        lines.join("\n")
+    }
+}
+#[test]
+fn test_wrap() {
+    let renderer = Renderer::plain(30);
+    assert_eq!(renderer.wrap("the quick brown fox jumps over the lazy dog", 4),
+               "    the quick brown fox jumps\n    over the lazy dog");
+    assert_eq!(renderer.wrap("a\n\n  indented words stay indented when wrapped", 0),
+               "a\n\n  indented words stay indented\n  when wrapped");
+    assert_eq!(renderer.wrap("https://example.com/a/very/long/link/to/somewhere", 2),
+               "  https://example.com/a/very/long/link/to/somewhere");
+}
+
+/// The number of characters in the widest of `ids`
+pub fn id_width<I: IntoIterator<Item = i32>>(ids: I) -> usize {
+    ids.into_iter().map(|id| id.to_string().len()).max().unwrap_or(1)
+}
+
+/// A duration as hours and minutes, padded to line up in a column, such as `  2h05m`.
+pub fn duration(seconds: f32) -> String {
+    let minutes = (seconds / 60f32).floor() as i64;
+    format!("{:4}h{:02}m", minutes / 60, minutes % 60)
+}
+
+/// Box-drawing prefixes such as `│   └── ` for rows listed depth-first at `depths`, where
+/// roots are at depth 0 and get no prefix.
+pub fn tree_guides(depths: &[usize]) -> Vec<String> {
+    // scan backwards to find which rows have a later sibling: `later[d]` is set once a row at
+    // depth d has been passed without going above d since
+    let mut has_sibling = vec![false; depths.len()];
+    let mut later: Vec<bool> = vec![];
+    for (i, &depth) in depths.iter().enumerate().rev() {
+        later.resize(depth + 1, false);
+        has_sibling[i] = later[depth];
+        later[depth] = true;
+    }
+
+    let mut result = vec![];
+    let mut ancestors: Vec<bool> = vec![];
+    for (i, &depth) in depths.iter().enumerate() {
+        ancestors.truncate(depth);
+        let mut prefix = String::new();
+        for &ancestor_has_sibling in ancestors.iter().skip(1) {
+            prefix.push_str(if ancestor_has_sibling { "│   " } else { "    " });
+        }
+        if depth > 0 {
+            prefix.push_str(if has_sibling[i] { "├── " } else { "└── " });
+        }
+        ancestors.push(has_sibling[i]);
+        result.push(prefix);
+    }
+    result
+}
+#[test]
+fn test_tree_guides() {
+    assert_eq!(tree_guides(&[0, 1, 2, 1, 2, 0, 1]),
+               vec!["", "├── ", "│   └── ", "└── ", "    └── ", "", "└── "]);
+}
@@ -0,0 +1,1003 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::*;
+
+use error::{Error, Result};
+use migrations::{Direction, Migration};
+use models::{Task, TaskAux, Note, NoteAux, Template, Review, Timer, SearchFilter,
+             SearchResult, TaskOrder, Dependency, Series, TreeNode};
+use store::Store;
+use store::text_search;
+
+/// A store that lives entirely in memory.
+///
+/// It mirrors the SQL backends, including `ON DELETE CASCADE` on `task.parent_id` and
+/// `note.task_id`, and keeps a log of every change made through it so that a command can be
+/// replayed against a snapshot and reported instead of written (see `--dry-run`).
+pub struct MemoryStore {
+    state: RefCell<State>,
+}
+
+struct State {
+    tasks: BTreeMap<i32, Task>,
+    notes: BTreeMap<i32, Note>,
+    templates: BTreeMap<String, String>,
+    /// (task_id, blocker_id)
+    dependencies: BTreeSet<(i32, i32)>,
+    series: BTreeMap<i32, Series>,
+    timer: Option<Timer>,
+    next_task_id: i32,
+    next_series_id: i32,
+    next_note_id: i32,
+    migrations: &'static [Migration],
+    applied_migrations: Vec<i32>,
+    changes: Vec<String>,
+}
+
+fn seconds_between(start: &DateTime<Local>, end: &DateTime<Local>) -> f32 {
+    (*end - *start).num_milliseconds() as f32 / 1000f32
+}
+
+fn note_aux(note: &Note) -> NoteAux {
+    NoteAux {
+        id: note.id,
+        task_id: note.task_id,
+        body: note.body.clone(),
+        date_start: note.date_start,
+        date_end: note.date_end,
+        duration_seconds: seconds_between(&note.date_start, &note.date_end),
+    }
+}
+
+fn clone_note(note: &Note) -> Note {
+    Note {
+        id: note.id,
+        task_id: note.task_id,
+        body: note.body.clone(),
+        date_start: note.date_start,
+        date_end: note.date_end,
+    }
+}
+
+fn sort_by_date_created_desc(tasks: &mut [Task]) {
+    tasks.sort_by_key(|task| ::std::cmp::Reverse(task.date_created));
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore {
+            state: RefCell::new(State {
+                                    tasks: BTreeMap::new(),
+                                    notes: BTreeMap::new(),
+                                    templates: BTreeMap::new(),
+                                    dependencies: BTreeSet::new(),
+                                    series: BTreeMap::new(),
+                                    timer: None,
+                                    next_task_id: 1,
+                                    next_series_id: 1,
+                                    next_note_id: 1,
+                                    migrations: &[],
+                                    applied_migrations: vec![],
+                                    changes: vec![],
+                                }),
+        }
+    }
+
+    /// Copies every task, note, template and the running timer out of `store`, keeping their
+    /// IDs, along with its migration history.
+    pub fn snapshot(store: &dyn Store) -> Result<MemoryStore> {
+        let memory = MemoryStore::new();
+        {
+            let mut state = memory.state.borrow_mut();
+            state.migrations = store.migrations();
+            state.applied_migrations = store.applied_migrations()?;
+            // A SQL store has no tables to copy from until its first migration has run
+            let has_tables = state.migrations.is_empty() || !state.applied_migrations.is_empty();
+            if has_tables {
+                for task in store.all_tasks()? {
+                    for note in store.find_notes(task.id)? {
+                        state.next_note_id = std::cmp::max(state.next_note_id, note.id + 1);
+                        state.notes.insert(note.id, note);
+                    }
+                    state.next_task_id = std::cmp::max(state.next_task_id, task.id + 1);
+                    state.tasks.insert(task.id, task);
+                }
+                for template in store.all_templates()? {
+                    state.templates.insert(template.name, template.body);
+                }
+                for dependency in store.all_dependencies()? {
+                    state.dependencies.insert((dependency.task_id, dependency.blocker_id));
+                }
+                for series in store.all_series()? {
+                    state.next_series_id = std::cmp::max(state.next_series_id, series.id + 1);
+                    state.series.insert(series.id, series);
+                }
+                state.timer = store.active_timer()?;
+            }
+        }
+        Ok(memory)
+    }
+
+    /// Describes, in order, the changes made through this store.
+    pub fn changes(&self) -> Vec<String> {
+        self.state.borrow().changes.clone()
+    }
+}
+
+impl State {
+    fn log(&mut self, change: String) {
+        self.changes.push(change);
+    }
+
+    fn delete_task(&mut self, id: i32) {
+        self.tasks.remove(&id);
+        self.dependencies.retain(|&(task_id, blocker_id)| task_id != id && blocker_id != id);
+        if self.timer.as_ref().is_some_and(|timer| timer.task_id == id) {
+            self.timer = None;
+        }
+        let note_ids: Vec<i32> = self.notes
+            .values()
+            .filter(|note| note.task_id == id)
+            .map(|note| note.id)
+            .collect();
+        for note_id in note_ids {
+            self.notes.remove(&note_id);
+        }
+        let child_ids: Vec<i32> = self.tasks
+            .values()
+            .filter(|task| task.parent_id == Some(id))
+            .map(|task| task.id)
+            .collect();
+        for child_id in child_ids {
+            self.delete_task(child_id);
+        }
+    }
+
+    fn insert_task(&mut self, task: &Task) -> Result<Task> {
+        if let Some(parent_id) = task.parent_id {
+            if !self.tasks.contains_key(&parent_id) {
+                return Err(Error::not_found("task", parent_id));
+            }
+        }
+        let id = self.next_task_id;
+        self.next_task_id += 1;
+        let mut task = task.clone();
+        task.id = id;
+        task.open = true;
+        let position = match task.parent_id {
+            Some(parent_id) => format!("under task {}", parent_id),
+            None => "as a root task".to_owned(),
+        };
+        self.log(format!("create task {} {:?} {}", id, task.title, position));
+        self.tasks.insert(id, task.clone());
+        Ok(task)
+    }
+
+    fn own_seconds(&self, id: i32) -> f32 {
+        self.notes
+            .values()
+            .filter(|note| note.task_id == id)
+            .map(|note| seconds_between(&note.date_start, &note.date_end))
+            .sum()
+    }
+
+    fn total_seconds(&self, id: i32) -> f32 {
+        let children: f32 = self.tasks
+            .values()
+            .filter(|task| task.parent_id == Some(id))
+            .map(|task| self.total_seconds(task.id))
+            .sum();
+        self.own_seconds(id) + children
+    }
+
+    fn task_aux(&self, task: &Task) -> TaskAux {
+        TaskAux {
+            id: task.id,
+            parent_id: task.parent_id,
+            title: task.title.clone(),
+            body: task.body.clone(),
+            open: task.open,
+            date_created: task.date_created,
+            date_finished: task.date_finished,
+            priority: task.priority,
+            due_date: task.due_date,
+            series_id: task.series_id,
+            tags: task.tags.clone(),
+            duration_seconds: self.own_seconds(task.id),
+            total_duration_seconds: self.total_seconds(task.id),
+        }
+    }
+}
+
+impl Store for MemoryStore {
+    fn migrations(&self) -> &'static [Migration] {
+        self.state.borrow().migrations
+    }
+
+    fn applied_migrations(&self) -> Result<Vec<i32>> {
+        Ok(self.state.borrow().applied_migrations.clone())
+    }
+
+    fn apply_migration(&self, migration: &Migration, direction: Direction) -> Result<()> {
+        let mut state = self.state.borrow_mut();
+        match direction {
+            Direction::Up => state.applied_migrations.push(migration.version),
+            Direction::Down => {
+                state.applied_migrations.retain(|version| *version != migration.version);
+                if state.applied_migrations.is_empty() {
+                    state.tasks.clear();
+                    state.notes.clear();
+                    state.templates.clear();
+                    state.dependencies.clear();
+                    state.series.clear();
+                }
+            }
+        }
+        let verb = match direction {
+            Direction::Up => "apply",
+            Direction::Down => "revert",
+        };
+        state.log(format!("{} migration {} ({})", verb, migration.version, migration.name));
+        Ok(())
+    }
+
+    fn create_task(&self, task: &Task) -> Result<Task> {
+        self.state.borrow_mut().insert_task(task)
+    }
+
+    fn delete_task(&self, id: i32) -> Result<()> {
+        let mut state = self.state.borrow_mut();
+        if !state.tasks.contains_key(&id) {
+            return Err(Error::not_found("task", id));
+        }
+        state.delete_task(id);
+        state.log(format!("delete task {} and its subtasks", id));
+        Ok(())
+    }
+
+    fn update_task(&self, id: i32, title: &str, body: &str) -> Result<()> {
+        let mut state = self.state.borrow_mut();
+        match state.tasks.get_mut(&id) {
+            Some(task) => {
+                task.title = title.to_owned();
+                task.body = body.to_owned();
+            }
+            None => return Err(Error::not_found("task", id)),
+        }
+        state.log(format!("edit task {}", id));
+        Ok(())
+    }
+
+    fn update_tags(&self, id: i32, add: &[String], remove: &[String]) -> Result<()> {
+        let mut state = self.state.borrow_mut();
+        match state.tasks.get_mut(&id) {
+            Some(task) => {
+                task.tags.extend(add.iter().cloned());
+                task.tags.retain(|tag| !remove.contains(tag));
+                task.tags.sort();
+                task.tags.dedup();
+            }
+            None => return Err(Error::not_found("task", id)),
+        }
+        let changes: Vec<String> = add.iter()
+            .map(|tag| format!("+{}", tag))
+            .chain(remove.iter().map(|tag| format!("-{}", tag)))
+            .collect();
+        state.log(format!("tag task {} {}", id, changes.join(" ")));
+        Ok(())
+    }
+
+    fn set_parent(&self, id: i32, parent_id: Option<i32>) -> Result<()> {
+        let mut state = self.state.borrow_mut();
+        if let Some(parent_id) = parent_id {
+            if !state.tasks.contains_key(&parent_id) {
+                return Err(Error::not_found("task", parent_id));
+            }
+        }
+        match state.tasks.get_mut(&id) {
+            Some(task) => task.parent_id = parent_id,
+            None => return Err(Error::not_found("task", id)),
+        }
+        let position = match parent_id {
+            Some(parent_id) => format!("under task {}", parent_id),
+            None => "to the root".to_owned(),
+        };
+        state.log(format!("move task {} {}", id, position));
+        Ok(())
+    }
+
+    fn add_dependency(&self, task_id: i32, blocker_id: i32) -> Result<()> {
+        let mut state = self.state.borrow_mut();
+        for id in &[task_id, blocker_id] {
+            if !state.tasks.contains_key(id) {
+                return Err(Error::not_found("task", id));
+            }
+        }
+        state.dependencies.insert((task_id, blocker_id));
+        state.log(format!("block task {} on task {}", task_id, blocker_id));
+        Ok(())
+    }
+
+    fn remove_dependency(&self, task_id: i32, blocker_id: i32) -> Result<()> {
+        let mut state = self.state.borrow_mut();
+        if !state.dependencies.remove(&(task_id, blocker_id)) {
+            return Err(Error::not_found("dependency",
+                                        format!("of task {} on task {}", task_id, blocker_id)));
+        }
+        state.log(format!("unblock task {} from task {}", task_id, blocker_id));
+        Ok(())
+    }
+
+    fn all_dependencies(&self) -> Result<Vec<Dependency>> {
+        Ok(self.state
+               .borrow()
+               .dependencies
+               .iter()
+               .map(|&(task_id, blocker_id)| {
+                        Dependency {
+                            task_id,
+                            blocker_id,
+                        }
+                    })
+               .collect())
+    }
+
+    fn finish_task(&self, id: i32, date_finished: DateTime<Local>) -> Result<()> {
+        let mut state = self.state.borrow_mut();
+        match state.tasks.get_mut(&id) {
+            Some(task) => {
+                task.open = false;
+                task.date_finished = task.date_finished.or(Some(date_finished));
+            }
+            None => return Err(Error::not_found("task", id)),
+        }
+        state.log(format!("finish task {}", id));
+        Ok(())
+    }
+
+    fn finish_recurring_task(&self,
+                             id: i32,
+                             date_finished: DateTime<Local>,
+                             next: &Task)
+                             -> Result<Task> {
+        // undo the finish if the next instance cannot be created, as the SQL transactions do
+        let previous = self.find_task(id)?.ok_or_else(|| Error::not_found("task", id))?;
+        let changes = self.state.borrow().changes.len();
+        self.finish_task(id, date_finished)?;
+        let mut state = self.state.borrow_mut();
+        state.insert_task(next).inspect_err(|_| {
+            state.tasks.insert(id, previous);
+            state.changes.truncate(changes);
+        })
+    }
+
+    fn reopen_task(&self, id: i32) -> Result<()> {
+        let mut state = self.state.borrow_mut();
+        match state.tasks.get_mut(&id) {
+            Some(task) => {
+                task.open = true;
+                task.date_finished = None;
+            }
+            None => return Err(Error::not_found("task", id)),
+        }
+        state.log(format!("reopen task {}", id));
+        Ok(())
+    }
+
+    fn all_tasks(&self) -> Result<Vec<Task>> {
+        let mut result: Vec<Task> = self.state.borrow().tasks.values().cloned().collect();
+        sort_by_date_created_desc(&mut result);
+        Ok(result)
+    }
+
+    fn find_task(&self, id: i32) -> Result<Option<Task>> {
+        Ok(self.state.borrow().tasks.get(&id).cloned())
+    }
+
+    fn find_task_aux(&self, id: i32) -> Result<Option<TaskAux>> {
+        let state = self.state.borrow();
+        Ok(state.tasks.get(&id).map(|task| state.task_aux(task)))
+    }
+
+    fn all_tasks_aux(&self) -> Result<Vec<TaskAux>> {
+        let tasks = self.all_tasks()?;
+        let state = self.state.borrow();
+        Ok(tasks.iter().map(|task| state.task_aux(task)).collect())
+    }
+
+    fn subtree(&self, root: Option<i32>, depth: Option<i32>) -> Result<Vec<TreeNode>> {
+        let state = self.state.borrow();
+        // tasks are kept in ID order, so pushing children in reverse pops them in ID order
+        let mut stack: Vec<TreeNode> = state.tasks
+            .values()
+            .filter(|task| match root {
+                        Some(root) => task.id == root,
+                        None => task.parent_id.is_none(),
+                    })
+            .rev()
+            .map(|task| {
+                     TreeNode {
+                         task: task.clone(),
+                         depth: 0,
+                         path: vec![task.id],
+                     }
+                 })
+            .collect();
+        let mut result = vec![];
+        while let Some(node) = stack.pop() {
+            if depth.is_none_or(|depth| node.depth < depth) {
+                for child in state.tasks
+                        .values()
+                        .rev()
+                        .filter(|task| task.parent_id == Some(node.task.id)) {
+                    if node.path.contains(&child.id) {
+                        continue;
+                    }
+                    let mut path = node.path.clone();
+                    path.push(child.id);
+                    stack.push(TreeNode {
+                                   task: child.clone(),
+                                   depth: node.depth + 1,
+                                   path,
+                               });
+                }
+            }
+            result.push(node);
+        }
+        Ok(result)
+    }
+
+    fn open_leaves(&self, order: TaskOrder) -> Result<Vec<Task>> {
+        let state = self.state.borrow();
+        let mut result: Vec<Task> = state.tasks
+            .values()
+            .filter(|task| task.open)
+            .filter(|task| {
+                !state.tasks.values().any(|child| child.parent_id == Some(task.id) && child.open)
+            })
+            .cloned()
+            .collect();
+        result.sort_by(|a, b| order.compare(a, b));
+        Ok(result)
+    }
+
+    fn find_recently_updated(&self, days: i32) -> Result<Vec<Review>> {
+        let state = self.state.borrow();
+        let since = Local::now() - Duration::days(days as i64);
+        let mut result: Vec<Review> = state.notes
+            .values()
+            .filter(|note| note.date_start > since)
+            .filter_map(|note| {
+                state.tasks.get(&note.task_id).map(|task| {
+                    Review {
+                        task_id: task.id,
+                        task_title: task.title.clone(),
+                        open: task.open,
+                        date_finished: task.date_finished,
+                        note_id: Some(note.id),
+                        note_body: Some(note.body.clone()),
+                        last_updated: note.date_start,
+                    }
+                })
+            })
+            .collect();
+        for task in state.tasks.values() {
+            let finished_recently = task.date_finished.is_some_and(|date| date > since);
+            if finished_recently && !result.iter().any(|review| review.task_id == task.id) {
+                result.push(Review {
+                                task_id: task.id,
+                                task_title: task.title.clone(),
+                                open: task.open,
+                                date_finished: task.date_finished,
+                                note_id: None,
+                                note_body: None,
+                                last_updated: task.date_finished.unwrap(),
+                            });
+            }
+        }
+        result.sort_by_key(|review| ::std::cmp::Reverse((review.task_id, review.note_id)));
+        Ok(result)
+    }
+
+    fn search(&self, query: &str, filter: &SearchFilter) -> Result<Vec<SearchResult>> {
+        let state = self.state.borrow();
+        let terms = text_search::terms(query);
+        let mut result = vec![];
+        for task in state.tasks.values() {
+            let text = format!("{} {}", task.title, task.body);
+            if let Some(rank) = text_search::rank(&text, &terms) {
+                if filter.matches(task.open, task.date_created) {
+                    result.push(SearchResult {
+                                    task_id: task.id,
+                                    task_title: task.title.clone(),
+                                    open: task.open,
+                                    note_id: None,
+                                    snippet: text_search::snippet(&text, &terms),
+                                    rank,
+                                    date: task.date_created,
+                                });
+                }
+            }
+        }
+        for note in state.notes.values() {
+            let task = &state.tasks[&note.task_id];
+            if let Some(rank) = text_search::rank(&note.body, &terms) {
+                if filter.matches(task.open, note.date_start) {
+                    result.push(SearchResult {
+                                    task_id: task.id,
+                                    task_title: task.title.clone(),
+                                    open: task.open,
+                                    note_id: Some(note.id),
+                                    snippet: text_search::snippet(&note.body, &terms),
+                                    rank,
+                                    date: note.date_start,
+                                });
+                }
+            }
+        }
+        text_search::sort_results(&mut result);
+        Ok(result)
+    }
+
+    fn create_note(&self,
+                   task_id: i32,
+                   body: &str,
+                   task_body: &str,
+                   date_start: DateTime<Local>,
+                   date_end: DateTime<Local>)
+                   -> Result<Note> {
+        let mut state = self.state.borrow_mut();
+        match state.tasks.get_mut(&task_id) {
+            Some(task) => task.body = task_body.to_owned(),
+            None => return Err(Error::not_found("task", task_id)),
+        }
+        let id = state.next_note_id;
+        state.next_note_id += 1;
+        let note = Note {
+            id,
+            task_id,
+            body: body.to_owned(),
+            date_start,
+            date_end,
+        };
+        state.notes.insert(id, clone_note(&note));
+        state.log(format!("add note {} to task {} and update the task's body", id, task_id));
+        Ok(note)
+    }
+
+    fn update_note(&self, id: i32, body: &str, task_body: &str) -> Result<()> {
+        let mut state = self.state.borrow_mut();
+        let task_id = match state.notes.get_mut(&id) {
+            Some(note) => {
+                note.body = body.to_owned();
+                note.task_id
+            }
+            None => return Err(Error::not_found("note", id)),
+        };
+        if let Some(task) = state.tasks.get_mut(&task_id) {
+            task.body = task_body.to_owned();
+        }
+        state.log(format!("edit note {} and the body of task {}", id, task_id));
+        Ok(())
+    }
+
+    fn start_timer(&self, task_id: i32, date_start: DateTime<Local>) -> Result<Timer> {
+        let mut state = self.state.borrow_mut();
+        if !state.tasks.contains_key(&task_id) {
+            return Err(Error::not_found("task", task_id));
+        }
+        if state.timer.is_some() {
+            return Err(Error::Validation("A timer is already running".to_owned()));
+        }
+        let timer = Timer {
+            task_id,
+            date_start,
+        };
+        state.timer = Some(timer.clone());
+        state.log(format!("start a timer for task {}", task_id));
+        Ok(timer)
+    }
+
+    fn active_timer(&self) -> Result<Option<Timer>> {
+        Ok(self.state.borrow().timer.clone())
+    }
+
+    fn stop_timer(&self,
+                  body: &str,
+                  task_body: &str,
+                  date_end: DateTime<Local>)
+                  -> Result<Note> {
+        let timer = match self.state.borrow().timer.clone() {
+            Some(timer) => timer,
+            None => return Err(Error::Validation("No timer is running".to_owned())),
+        };
+        // keep the timer running if the note cannot be created, as the SQL transactions do
+        let note = self.create_note(timer.task_id, body, task_body, timer.date_start, date_end)?;
+        let mut state = self.state.borrow_mut();
+        state.timer = None;
+        state.log(format!("stop the timer for task {}", timer.task_id));
+        Ok(note)
+    }
+
+    fn delete_note(&self, id: i32) -> Result<()> {
+        let mut state = self.state.borrow_mut();
+        if state.notes.remove(&id).is_none() {
+            return Err(Error::not_found("note", id));
+        }
+        state.log(format!("delete note {}", id));
+        Ok(())
+    }
+
+    fn find_note(&self, id: i32) -> Result<Option<Note>> {
+        Ok(self.state.borrow().notes.get(&id).map(clone_note))
+    }
+
+    fn find_notes(&self, task_id: i32) -> Result<Vec<Note>> {
+        let state = self.state.borrow();
+        let mut result: Vec<Note> = state.notes
+            .values()
+            .filter(|note| note.task_id == task_id)
+            .map(clone_note)
+            .collect();
+        result.sort_by_key(|a| a.date_start);
+        Ok(result)
+    }
+
+    fn find_notes_aux(&self, task_id: i32) -> Result<Vec<NoteAux>> {
+        Ok(self.find_notes(task_id)?.iter().map(note_aux).collect())
+    }
+
+    fn find_notes_between(&self,
+                          from: DateTime<Local>,
+                          to: DateTime<Local>)
+                          -> Result<Vec<NoteAux>> {
+        let state = self.state.borrow();
+        let mut result: Vec<NoteAux> = state.notes
+            .values()
+            .filter(|note| note.date_start >= from && note.date_start < to)
+            .map(note_aux)
+            .collect();
+        result.sort_by_key(|a| (a.task_id, a.date_start));
+        Ok(result)
+    }
+
+    fn latest_note_dates(&self) -> Result<Vec<(i32, DateTime<Local>)>> {
+        let state = self.state.borrow();
+        let mut latest: BTreeMap<i32, DateTime<Local>> = BTreeMap::new();
+        for note in state.notes.values() {
+            let date = latest.entry(note.task_id).or_insert(note.date_start);
+            if note.date_start > *date {
+                *date = note.date_start;
+            }
+        }
+        Ok(latest.into_iter().collect())
+    }
+
+    fn create_series(&self, task_id: i32, recurrence: &str, body: &str) -> Result<Series> {
+        let mut state = self.state.borrow_mut();
+        let id = state.next_series_id;
+        match state.tasks.get_mut(&task_id) {
+            Some(task) => task.series_id = Some(id),
+            None => return Err(Error::not_found("task", task_id)),
+        }
+        state.next_series_id += 1;
+        let series = Series {
+            id,
+            recurrence: recurrence.to_owned(),
+            body: body.to_owned(),
+        };
+        state.series.insert(id, series.clone());
+        state.log(format!("repeat task {} {}", task_id, recurrence));
+        Ok(series)
+    }
+
+    fn set_series(&self, task_id: i32, series_id: Option<i32>) -> Result<()> {
+        let mut state = self.state.borrow_mut();
+        match state.tasks.get_mut(&task_id) {
+            Some(task) => task.series_id = series_id,
+            None => return Err(Error::not_found("task", task_id)),
+        }
+        match series_id {
+            Some(series_id) => state.log(format!("add task {} to series {}", task_id, series_id)),
+            None => state.log(format!("stop repeating task {}", task_id)),
+        }
+        Ok(())
+    }
+
+    fn find_series(&self, id: i32) -> Result<Option<Series>> {
+        Ok(self.state.borrow().series.get(&id).cloned())
+    }
+
+    fn all_series(&self) -> Result<Vec<Series>> {
+        Ok(self.state.borrow().series.values().cloned().collect())
+    }
+
+    fn upsert_template(&self, name: &str, body: &str) -> Result<()> {
+        let mut state = self.state.borrow_mut();
+        state.templates.insert(name.to_owned(), body.to_owned());
+        state.log(format!("save template {:?}", name));
+        Ok(())
+    }
+
+    fn find_template(&self, name: &str) -> Result<Option<String>> {
+        Ok(self.state.borrow().templates.get(name).cloned())
+    }
+
+    fn all_templates(&self) -> Result<Vec<Template>> {
+        let templates = self.state
+            .borrow()
+            .templates
+            .iter()
+            .map(|(name, body)| {
+                Template {
+                    name: name.clone(),
+                    body: body.clone(),
+                }
+            })
+            .collect();
+        Ok(templates)
+    }
+}
+
+/// Root (1) > Child (2) > Grandchild (3), and Other (4), created a second apart
+#[cfg(test)]
+pub(crate) fn sample_store() -> MemoryStore {
+    let store = MemoryStore::new();
+    let now = Local::now();
+    Task::new(None, "Root", "", now).create(&store).unwrap();
+    Task::new(Some(1), "Child", "", now + Duration::seconds(1)).create(&store).unwrap();
+    Task::new(Some(2), "Grandchild", "", now + Duration::seconds(2)).create(&store).unwrap();
+    Task::new(None, "Other", "", now + Duration::seconds(3)).create(&store).unwrap();
+    store
+}
+
+#[test]
+fn test_delete_cascades() {
+    let store = sample_store();
+    let now = Local::now();
+    Note::create(&store, 3, "note", "body", now, now + Duration::minutes(5)).unwrap();
+    Task::delete(&store, 2).unwrap();
+
+    let ids: Vec<i32> = Task::all(&store).unwrap().iter().map(|t| t.id).collect();
+    assert_eq!(ids, vec![4, 1]);
+    assert!(Task::find_notes(&store, 3).unwrap().is_empty());
+}
+
+#[test]
+fn test_open_leaves() {
+    let store = sample_store();
+    let ids = |store: &MemoryStore| -> Vec<i32> {
+        Task::open_leaves(store, TaskOrder::Created).unwrap().iter().map(|t| t.id).collect()
+    };
+    assert_eq!(ids(&store), vec![4, 3]);
+
+    Task::finish(&store, 3).unwrap();
+    assert_eq!(ids(&store), vec![4, 2]);
+}
+
+#[test]
+fn test_durations_and_reviews() {
+    let store = sample_store();
+    let start = Local::now() - Duration::hours(3);
+    Note::create(&store, 2, "first", "b1", start, start + Duration::minutes(30)).unwrap();
+    Note::create(&store, 2, "second", "b2", start, start + Duration::hours(1)).unwrap();
+
+    Note::create(&store, 3, "third", "c", start, start + Duration::minutes(30)).unwrap();
+
+    let task = Task::find_aux(&store, 2).unwrap();
+    assert_eq!(task.duration_seconds, 5400f32);
+    assert_eq!(task.total_duration_seconds, 7200f32);
+    assert_eq!(task.body, "b2");
+    let totals: Vec<(i32, f32, f32)> = Task::all_aux(&store)
+        .unwrap()
+        .iter()
+        .map(|t| (t.id, t.duration_seconds, t.total_duration_seconds))
+        .collect();
+    assert_eq!(totals,
+               vec![(4, 0f32, 0f32),
+                    (3, 1800f32, 1800f32),
+                    (2, 5400f32, 7200f32),
+                    (1, 0f32, 7200f32)]);
+
+    let reviews = Task::find_recently_updated(&store, 1).unwrap();
+    let note_ids: Vec<Option<i32>> = reviews.iter().map(|r| r.note_id).collect();
+    assert_eq!(note_ids, vec![Some(3), Some(2), Some(1)]);
+    assert!(Task::find_recently_updated(&store, 0).unwrap().is_empty());
+}
+
+#[test]
+fn test_finish_and_reopen() {
+    let store = sample_store();
+    Task::finish(&store, 3).unwrap();
+    let date_finished = Task::find(&store, 3).unwrap().date_finished.unwrap();
+    Task::finish(&store, 3).unwrap();
+    assert_eq!(Task::find(&store, 3).unwrap().date_finished, Some(date_finished));
+
+    let reviews = Task::find_recently_updated(&store, 1).unwrap();
+    assert_eq!(reviews.len(), 1);
+    assert_eq!((reviews[0].task_id, reviews[0].note_id), (3, None));
+
+    Task::reopen(&store, 3).unwrap();
+    let task = Task::find(&store, 3).unwrap();
+    assert!(task.open && task.date_finished.is_none());
+    assert!(Task::find_recently_updated(&store, 1).unwrap().is_empty());
+}
+
+#[test]
+fn test_missing_rows_and_validation() {
+    let store = sample_store();
+    let now = Local::now();
+    match Task::finish(&store, 42) {
+        Err(Error::NotFound(ref what)) => assert_eq!(what, "task 42"),
+        other => panic!("unexpected {:?}", other),
+    }
+    match Note::create(&store, 1, "", "", now, now - Duration::seconds(1)) {
+        Err(Error::Validation(_)) => {}
+        other => panic!("unexpected {:?}", other),
+    }
+    assert!(Note::create(&store, 1, "", "", now, now).is_err());
+    assert!(Task::new(Some(42), "Orphan", "", now).create(&store).is_err());
+    assert!(Task::new(None, "  ", "", now).create(&store).is_err());
+}
+
+#[test]
+fn test_update_rows() {
+    let store = sample_store();
+    let now = Local::now();
+    let note = Note::create(&store, 2, "note", "body", now, now + Duration::minutes(5)).unwrap();
+    Task::update(&store, 2, "Renamed", "new body").unwrap();
+    Note::update(&store, note.id, "edited", "edited body").unwrap();
+
+    let task = Task::find(&store, 2).unwrap();
+    assert_eq!((task.title.as_str(), task.body.as_str()), ("Renamed", "edited body"));
+    assert_eq!(Note::find(&store, note.id).unwrap().body, "edited");
+    assert!(Task::update(&store, 2, "", "").is_err());
+    assert!(Note::update(&store, 42, "", "").is_err());
+}
+
+#[test]
+fn test_tags() {
+    let store = sample_store();
+    Task::tag(&store, 2, &["+work", "urgent", "+work"]).unwrap();
+    Task::tag(&store, 2, &["-urgent", "-missing", "+home"]).unwrap();
+
+    let task = Task::find(&store, 2).unwrap();
+    assert_eq!(task.tags, vec!["home", "work"]);
+    assert_eq!(task.to_string(), "  2: Child #home #work");
+    assert_eq!(Task::find_aux(&store, 2).unwrap().tags, task.tags);
+    assert!(task.has_tags(&["work".to_owned()]));
+    assert!(!task.has_tags(&["work".to_owned(), "urgent".to_owned()]));
+    assert!(Task::tag(&store, 2, &["+"]).is_err());
+    assert!(Task::tag(&store, 42, &["+work"]).is_err());
+}
+
+#[test]
+fn test_report() {
+    let store = sample_store();
+    let start = Local::now() - Duration::days(2);
+    Note::create(&store, 3, "old", "", start, start + Duration::hours(1)).unwrap();
+    Note::create(&store, 1, "a", "", start, start + Duration::hours(1)).unwrap();
+    Note::create(&store, 3, "b", "", start, start + Duration::minutes(30)).unwrap();
+    let next_day = start + Duration::days(1);
+    Note::create(&store, 3, "c", "", next_day, next_day + Duration::minutes(30)).unwrap();
+
+    let reports = Task::report(&store, start, start + Duration::hours(1)).unwrap();
+    let summary: Vec<(i32, usize, f32)> = reports.iter()
+        .map(|r| (r.task.id, r.notes.len(), r.duration_seconds))
+        .collect();
+    assert_eq!(summary, vec![(1, 1, 3600f32), (3, 2, 5400f32)]);
+}
+
+#[test]
+fn test_timer() {
+    let store = sample_store();
+    assert!(Timer::start(&store, 42).is_err());
+    let timer = Timer::start(&store, 3).unwrap();
+    match Timer::start(&store, 2) {
+        Err(Error::Validation(ref reason)) => assert!(reason.ends_with("task 3")),
+        other => panic!("unexpected {:?}", other),
+    }
+    assert_eq!(Timer::active(&store).unwrap().map(|t| t.task_id), Some(3));
+    assert!(Timer::stop(&store, "", "", timer.date_start - Duration::minutes(1)).is_err());
+    assert!(Timer::active(&store).unwrap().is_some());
+
+    let date_end = timer.date_start + Duration::minutes(25);
+    let note = Timer::stop(&store, "done", "body", date_end).unwrap();
+    assert_eq!((note.task_id, note.date_start, note.date_end),
+               (3, timer.date_start, date_end));
+    assert!(Timer::active(&store).unwrap().is_none());
+    assert!(Timer::stop(&store, "", "", date_end).is_err());
+
+    // a timer left on a missing task fails to stop, and keeps running
+    store.state.borrow_mut().timer = Some(Timer { task_id: 42, date_start: timer.date_start });
+    assert!(store.stop_timer("", "", date_end).is_err());
+    assert!(Timer::active(&store).unwrap().is_some());
+    store.state.borrow_mut().timer = None;
+
+    Timer::start(&store, 3).unwrap();
+    Task::delete(&store, 2).unwrap();
+    assert!(Timer::active(&store).unwrap().is_none());
+}
+
+#[test]
+fn test_search() {
+    let store = sample_store();
+    let now = Local::now();
+    Task::update(&store, 3, "Fix the login bug", "It only happens on Mondays").unwrap();
+    let end = now + Duration::minutes(5);
+    Note::create(&store, 2, "Reproduced the bug, twice the bug", "", now, end).unwrap();
+    Note::create(&store, 4, "Nothing to see", "", now, end).unwrap();
+    Task::finish(&store, 2).unwrap();
+
+    let ids = |query: &str, filter: &SearchFilter| -> Vec<(i32, Option<i32>)> {
+        Task::search(&store, query, filter)
+            .unwrap()
+            .iter()
+            .map(|r| (r.task_id, r.note_id))
+            .collect()
+    };
+    let all = SearchFilter::default();
+    assert_eq!(ids("BUG", &all), vec![(2, Some(1)), (3, None)]);
+    assert_eq!(ids("bug mondays", &all), vec![(3, None)]);
+    let open = SearchFilter { open: Some(true), ..SearchFilter::default() };
+    assert_eq!(ids("bug", &open), vec![(3, None)]);
+    let later = SearchFilter { from: Some(now + Duration::days(1)), ..SearchFilter::default() };
+    assert!(ids("bug", &later).is_empty());
+    assert!(Task::search(&store, "  ", &all).is_err());
+}
+
+#[test]
+fn test_subtree() {
+    let store = sample_store();
+    Task::new(Some(1), "Second child", "", Local::now()).create(&store).unwrap();
+    let nodes: Vec<(i32, i32, Vec<i32>)> = Task::subtree(&store, None, None)
+        .unwrap()
+        .into_iter()
+        .map(|node| (node.task.id, node.depth, node.path))
+        .collect();
+    assert_eq!(nodes,
+               vec![(1, 0, vec![1]),
+                    (2, 1, vec![1, 2]),
+                    (3, 2, vec![1, 2, 3]),
+                    (5, 1, vec![1, 5]),
+                    (4, 0, vec![4])]);
+    let ids = |root, depth| -> Vec<i32> {
+        Task::subtree(&store, root, depth).unwrap().iter().map(|node| node.task.id).collect()
+    };
+    assert_eq!(ids(Some(2), None), vec![2, 3]);
+    assert_eq!(ids(Some(1), Some(1)), vec![1, 2, 5]);
+    assert!(Task::subtree(&store, None, Some(-1)).is_err());
+}
+
+#[test]
+fn test_finish_recurring_task_is_atomic() {
+    let store = sample_store();
+    let changes = store.changes();
+    let orphan = Task::new(Some(42), "Orphan", "", Local::now());
+    assert!(store.finish_recurring_task(2, Local::now(), &orphan).is_err());
+
+    let task = Task::find(&store, 2).unwrap();
+    assert!(task.open && task.date_finished.is_none());
+    assert_eq!(store.changes(), changes);
+}
+
+#[test]
+fn test_snapshot_records_changes() {
+    let store = sample_store();
+    let snapshot = MemoryStore::snapshot(&store).unwrap();
+    assert!(snapshot.changes().is_empty());
+
+    Task::new(Some(4), "New", "", Local::now()).create(&snapshot).unwrap();
+    Task::finish(&snapshot, 4).unwrap();
+
+    assert_eq!(Task::find(&snapshot, 5).unwrap().parent_id, Some(4));
+    assert!(Task::find(&store, 5).is_err());
+    assert_eq!(snapshot.changes().len(), 2);
+}
@@ -0,0 +1,128 @@
+use chrono::*;
+
+use error::Result;
+use migrations::{Direction, Migration};
+use models::{Task, TaskAux, Note, NoteAux, Template, Review, Timer, SearchFilter,
+             SearchResult, TaskOrder, Dependency, Series, TreeNode};
+
+pub mod memory;
+pub mod pg;
+pub mod sqlite;
+/// A plain substring search for the backends without full-text search: every word of the query
+/// has to appear, case-insensitively, and more occurrences rank higher.
+mod text_search;
+
+/// Reads the space-separated tags that the SQL backends aggregate into one column.
+fn split_tags(tags: Option<String>) -> Vec<String> {
+    tags.map_or(vec![], |tags| tags.split(' ').map(|tag| tag.to_owned()).collect())
+}
+
+/// The `ORDER BY` expressions matching `TaskOrder::compare`, for the SQL backends.
+fn order_by(order: TaskOrder) -> &'static str {
+    match order {
+        TaskOrder::Created => "date_created DESC",
+        TaskOrder::Due => "due_date NULLS LAST, priority NULLS LAST, date_created DESC",
+        TaskOrder::Priority => "priority NULLS LAST, due_date NULLS LAST, date_created DESC",
+    }
+}
+
+/// A storage backend for tasks, notes and templates.
+///
+/// The functions in `models` are thin wrappers around this trait, so anything that implements it
+/// can be handed to them (and to the CLI) in place of a Postgres connection.
+///
+/// Operations on a single row by ID fail with `Error::NotFound` when the row does not exist, while
+/// lookups return `None`.
+pub trait Store {
+    /// This backend's migrations, ordered by version.
+    fn migrations(&self) -> &'static [Migration];
+    /// The versions recorded in `schema_version`, empty if no migration has run yet.
+    fn applied_migrations(&self) -> Result<Vec<i32>>;
+    /// Runs one migration and records (or forgets) its version in the same transaction.
+    fn apply_migration(&self, migration: &Migration, direction: Direction) -> Result<()>;
+
+    /// Inserts `task` with its tags, ignoring its `id` and `open` fields, and returns the stored
+    /// row.
+    fn create_task(&self, task: &Task) -> Result<Task>;
+    fn delete_task(&self, id: i32) -> Result<()>;
+    fn update_task(&self, id: i32, title: &str, body: &str) -> Result<()>;
+    /// Adds and removes tags in one transaction. Adding a tag twice, or removing a missing one,
+    /// is not an error.
+    fn update_tags(&self, id: i32, add: &[String], remove: &[String]) -> Result<()>;
+    /// Moves the task, with its subtree, under `parent_id` or to the root. Does not check for
+    /// cycles; see `Task::set_parent`.
+    fn set_parent(&self, id: i32, parent_id: Option<i32>) -> Result<()>;
+    /// Makes `task_id` wait on `blocker_id`; adding an existing dependency is not an error. Does
+    /// not check for cycles; see `Task::block`.
+    fn add_dependency(&self, task_id: i32, blocker_id: i32) -> Result<()>;
+    fn remove_dependency(&self, task_id: i32, blocker_id: i32) -> Result<()>;
+    /// Ordered by task ID, then blocker ID.
+    fn all_dependencies(&self) -> Result<Vec<Dependency>>;
+    /// Closes the task, setting `date_finished` unless it is already set.
+    fn finish_task(&self, id: i32, date_finished: DateTime<Local>) -> Result<()>;
+    /// Closes the task and inserts `next`, the following instance of its series, in one
+    /// transaction, returning the new row.
+    fn finish_recurring_task(&self,
+                             id: i32,
+                             date_finished: DateTime<Local>,
+                             next: &Task)
+                             -> Result<Task>;
+    fn reopen_task(&self, id: i32) -> Result<()>;
+    fn all_tasks(&self) -> Result<Vec<Task>>;
+    fn find_task(&self, id: i32) -> Result<Option<Task>>;
+    fn find_task_aux(&self, id: i32) -> Result<Option<TaskAux>>;
+    /// Every task with its own and accumulated durations, newest first.
+    fn all_tasks_aux(&self) -> Result<Vec<TaskAux>>;
+    /// See `Task::subtree`, which checks that `root` exists.
+    fn subtree(&self, root: Option<i32>, depth: Option<i32>) -> Result<Vec<TreeNode>>;
+    fn open_leaves(&self, order: TaskOrder) -> Result<Vec<Task>>;
+    /// Notes started, and tasks finished, in the last `days` days, ordered by task ID then note ID,
+    /// both descending.
+    fn find_recently_updated(&self, days: i32) -> Result<Vec<Review>>;
+    /// Tasks (by title and body) and notes (by body) matching `query`, ordered by descending
+    /// rank, then task ID, with a task's own match before its notes.
+    fn search(&self, query: &str, filter: &SearchFilter) -> Result<Vec<SearchResult>>;
+
+    /// Inserts a note and replaces its task's body in one transaction, returning the new note.
+    fn create_note(&self,
+                   task_id: i32,
+                   body: &str,
+                   task_body: &str,
+                   date_start: DateTime<Local>,
+                   date_end: DateTime<Local>)
+                   -> Result<Note>;
+    /// Fails if a timer is already running; there is at most one at a time.
+    fn start_timer(&self, task_id: i32, date_start: DateTime<Local>) -> Result<Timer>;
+    fn active_timer(&self) -> Result<Option<Timer>>;
+    /// Removes the running timer and records it as a note ending at `date_end`, replacing its
+    /// task's body, in one transaction.
+    fn stop_timer(&self,
+                  body: &str,
+                  task_body: &str,
+                  date_end: DateTime<Local>)
+                  -> Result<Note>;
+    /// Replaces a note's body and its task's body in one transaction.
+    fn update_note(&self, id: i32, body: &str, task_body: &str) -> Result<()>;
+    fn delete_note(&self, id: i32) -> Result<()>;
+    fn find_note(&self, id: i32) -> Result<Option<Note>>;
+    fn find_notes(&self, task_id: i32) -> Result<Vec<Note>>;
+    fn find_notes_aux(&self, task_id: i32) -> Result<Vec<NoteAux>>;
+    /// Notes started at or after `from` and before `to`, ordered by task ID then start date.
+    fn find_notes_between(&self,
+                          from: DateTime<Local>,
+                          to: DateTime<Local>)
+                          -> Result<Vec<NoteAux>>;
+    /// The start of each task's latest note, for tasks with notes, in task ID order.
+    fn latest_note_dates(&self) -> Result<Vec<(i32, DateTime<Local>)>>;
+
+    /// Inserts a series and makes the task its first instance in one transaction.
+    fn create_series(&self, task_id: i32, recurrence: &str, body: &str) -> Result<Series>;
+    /// Links the task to a series, or unlinks it.
+    fn set_series(&self, task_id: i32, series_id: Option<i32>) -> Result<()>;
+    fn find_series(&self, id: i32) -> Result<Option<Series>>;
+    fn all_series(&self) -> Result<Vec<Series>>;
+
+    fn upsert_template(&self, name: &str, body: &str) -> Result<()>;
+    fn find_template(&self, name: &str) -> Result<Option<String>>;
+    fn all_templates(&self) -> Result<Vec<Template>>;
+}
@@ -0,0 +1,791 @@
+use chrono::*;
+use postgres::Connection;
+use postgres::rows::Row;
+use postgres::transaction::Transaction;
+
+use error::{Error, Result};
+use migrations::{Direction, Migration};
+use models::{Task, TaskAux, Note, NoteAux, Template, Review, Timer, SearchFilter,
+             SearchResult, TaskOrder, Dependency, Series, TreeNode};
+use store::{Store, split_tags, order_by};
+
+/// The columns `unpack_task` expects, in order
+const TASK_COLUMNS: &str = "task.id, task.parent_id, task.title, task.body, task.open, \
+                            task.date_created, task.date_finished, task.priority, \
+                            task.due_date, task.series_id, (
+    SELECT string_agg(tag, ' ' ORDER BY tag) FROM task_tag WHERE task_tag.task_id = task.id
+    )";
+
+fn unpack_task(row: Row) -> Task {
+    Task {
+        id: row.get(0),
+        parent_id: row.get(1),
+        title: row.get(2),
+        body: row.get(3),
+        open: row.get(4),
+        date_created: row.get(5),
+        date_finished: row.get(6),
+        priority: row.get(7),
+        due_date: row.get(8),
+        series_id: row.get(9),
+        tags: split_tags(row.get(10)),
+    }
+}
+
+fn unpack_task_aux(row: Row) -> TaskAux {
+    TaskAux {
+        id: row.get(0),
+        parent_id: row.get(1),
+        title: row.get(2),
+        body: row.get(3),
+        open: row.get(4),
+        date_created: row.get(5),
+        date_finished: row.get(6),
+        priority: row.get(7),
+        due_date: row.get(8),
+        series_id: row.get(9),
+        tags: split_tags(row.get(10)),
+        duration_seconds: row.get(11),
+        total_duration_seconds: row.get(12),
+    }
+}
+
+/// Selects the tasks matching `filter` (a `WHERE` clause, or nothing for every task) with their
+/// own durations and the durations of their whole subtrees, for `unpack_task_aux`.
+fn task_aux_query(filter: &str) -> String {
+    format!("
+WITH RECURSIVE subtree(root_id, id) AS (
+    SELECT task.id, task.id FROM task {filter}
+    UNION ALL
+    SELECT subtree.root_id, task.id FROM subtree JOIN task ON task.parent_id = subtree.id
+), own AS (
+    SELECT task_id, SUM(EXTRACT(EPOCH FROM date_end - date_start)) AS seconds
+    FROM note GROUP BY task_id
+)
+SELECT {columns},
+    COALESCE((SELECT seconds FROM own WHERE own.task_id = task.id), 0)::REAL,
+    COALESCE((SELECT SUM(own.seconds) FROM subtree JOIN own ON own.task_id = subtree.id
+              WHERE subtree.root_id = task.id), 0)::REAL
+FROM task {filter}
+ORDER BY date_created DESC",
+            filter = filter,
+            columns = TASK_COLUMNS)
+}
+
+/// Inserts the task with its tags, returning its new ID.
+fn insert_task(trans: &Transaction, task: &Task) -> Result<i32> {
+    let id: i32 = {
+        let rows = trans.query("
+INSERT INTO task(parent_id, title, body, date_created, priority, due_date, series_id)
+VALUES ($1, $2, $3, $4, $5, $6, $7)
+RETURNING id",
+                               &[&task.parent_id,
+                                 &task.title,
+                                 &task.body,
+                                 &task.date_created,
+                                 &task.priority,
+                                 &task.due_date,
+                                 &task.series_id])?;
+        rows.get(0).get(0)
+    };
+    for tag in &task.tags {
+        trans.execute("INSERT INTO task_tag(task_id, tag) VALUES ($1, $2)", &[&id, tag])?;
+    }
+    Ok(id)
+}
+
+fn unpack_series(row: Row) -> Series {
+    Series {
+        id: row.get(0),
+        recurrence: row.get(1),
+        body: row.get(2),
+    }
+}
+
+fn unpack_note(row: Row) -> Note {
+    Note {
+        id: row.get(0),
+        task_id: row.get(1),
+        body: row.get(2),
+        date_start: row.get(3),
+        date_end: row.get(4),
+    }
+}
+
+fn expect_one_row(updated: u64, kind: &str, id: i32) -> Result<()> {
+    if updated == 0 {
+        Err(Error::not_found(kind, id))
+    } else {
+        Ok(())
+    }
+}
+
+/// Version 1 keeps `IF NOT EXISTS` so that databases created before `schema_version` existed can
+/// adopt it without losing data.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "create task, note and template tables",
+        up: "
+CREATE TABLE IF NOT EXISTS task (
+    id              SERIAL PRIMARY KEY,
+    parent_id       INTEGER REFERENCES task(id) ON DELETE CASCADE,
+    title           TEXT NOT NULL,
+    body            TEXT NOT NULL,
+    open            BOOL NOT NULL DEFAULT TRUE,
+    date_created    TIMESTAMP WITH TIME ZONE NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS note (
+    id          SERIAL PRIMARY KEY,
+    task_id     INTEGER REFERENCES task(id) ON DELETE CASCADE,
+    body        TEXT NOT NULL,
+    date_start  TIMESTAMP WITH TIME ZONE NOT NULL,
+    date_end    TIMESTAMP WITH TIME ZONE NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS template (
+    name    TEXT PRIMARY KEY,
+    body    TEXT NOT NULL
+);
+",
+        down: "
+DROP TABLE IF EXISTS note;
+DROP TABLE IF EXISTS task;
+DROP TABLE IF EXISTS template;
+",
+    },
+    Migration {
+        version: 2,
+        name: "add task.date_finished",
+        up: "ALTER TABLE task ADD COLUMN date_finished TIMESTAMP WITH TIME ZONE;",
+        down: "ALTER TABLE task DROP COLUMN date_finished;",
+    },
+    Migration {
+        version: 3,
+        name: "create timer table",
+        up: "
+CREATE TABLE timer (
+    id          INTEGER PRIMARY KEY DEFAULT 1 CHECK (id = 1),
+    task_id     INTEGER NOT NULL REFERENCES task(id) ON DELETE CASCADE,
+    date_start  TIMESTAMP WITH TIME ZONE NOT NULL
+);
+",
+        down: "DROP TABLE timer;",
+    },
+    Migration {
+        version: 4,
+        name: "add full-text search indexes",
+        up: "
+CREATE INDEX task_search_idx ON task USING GIN (to_tsvector('english', title || ' ' || body));
+CREATE INDEX note_search_idx ON note USING GIN (to_tsvector('english', body));
+",
+        down: "
+DROP INDEX task_search_idx;
+DROP INDEX note_search_idx;
+",
+    },
+    Migration {
+        version: 5,
+        name: "create task_tag table",
+        up: "
+CREATE TABLE task_tag (
+    task_id     INTEGER NOT NULL REFERENCES task(id) ON DELETE CASCADE,
+    tag         TEXT NOT NULL,
+    PRIMARY KEY (task_id, tag)
+);
+",
+        down: "DROP TABLE task_tag;",
+    },
+    Migration {
+        version: 6,
+        name: "add task.priority and task.due_date",
+        up: "ALTER TABLE task ADD COLUMN priority INTEGER, ADD COLUMN due_date DATE;",
+        down: "ALTER TABLE task DROP COLUMN priority, DROP COLUMN due_date;",
+    },
+    Migration {
+        version: 7,
+        name: "create task_dependency table",
+        up: "
+CREATE TABLE task_dependency (
+    task_id     INTEGER NOT NULL REFERENCES task(id) ON DELETE CASCADE,
+    blocker_id  INTEGER NOT NULL REFERENCES task(id) ON DELETE CASCADE,
+    PRIMARY KEY (task_id, blocker_id),
+    CHECK (task_id <> blocker_id)
+);
+",
+        down: "DROP TABLE task_dependency;",
+    },
+    Migration {
+        version: 8,
+        name: "create task_series table and add task.series_id",
+        up: "
+CREATE TABLE task_series (
+    id          SERIAL PRIMARY KEY,
+    recurrence  TEXT NOT NULL,
+    body        TEXT NOT NULL
+);
+
+ALTER TABLE task ADD COLUMN series_id INTEGER REFERENCES task_series(id) ON DELETE SET NULL;
+",
+        down: "
+ALTER TABLE task DROP COLUMN series_id;
+DROP TABLE task_series;
+",
+    },
+];
+
+impl Store for Connection {
+    fn migrations(&self) -> &'static [Migration] {
+        MIGRATIONS
+    }
+
+    fn applied_migrations(&self) -> Result<Vec<i32>> {
+        let rows = &self.query("SELECT to_regclass('schema_version') IS NOT NULL", &[])?;
+        let exists: bool = rows.get(0).get(0);
+        if !exists {
+            return Ok(vec![]);
+        }
+
+        let mut result = vec![];
+        for row in &self.query("SELECT version FROM schema_version ORDER BY version", &[])? {
+            result.push(row.get(0));
+        }
+        Ok(result)
+    }
+
+    fn apply_migration(&self, migration: &Migration, direction: Direction) -> Result<()> {
+        let trans = self.transaction()?;
+
+        trans.batch_execute("
+CREATE TABLE IF NOT EXISTS schema_version (
+    version     INTEGER PRIMARY KEY,
+    name        TEXT NOT NULL,
+    date_applied TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+")?;
+        match direction {
+            Direction::Up => {
+                trans.batch_execute(migration.up)?;
+                trans.execute("INSERT INTO schema_version(version, name) VALUES ($1, $2)",
+                             &[&migration.version, &migration.name])?;
+            }
+            Direction::Down => {
+                trans.batch_execute(migration.down)?;
+                trans.execute("DELETE FROM schema_version WHERE version = $1",
+                             &[&migration.version])?;
+            }
+        }
+        trans.commit()?;
+        Ok(())
+    }
+
+    fn create_task(&self, task: &Task) -> Result<Task> {
+        let trans = self.transaction()?;
+
+        let id = insert_task(&trans, task)?;
+        trans.commit()?;
+        self.find_task(id)?.ok_or_else(|| Error::not_found("task", id))
+    }
+
+    fn delete_task(&self, id: i32) -> Result<()> {
+        let updated = self.execute("DELETE FROM task WHERE id = $1", &[&id])?;
+        expect_one_row(updated, "task", id)
+    }
+
+    fn update_task(&self, id: i32, title: &str, body: &str) -> Result<()> {
+        let updated = self.execute("UPDATE task SET title = $2, body = $3 WHERE id = $1",
+                                   &[&id, &title, &body])?;
+        expect_one_row(updated, "task", id)
+    }
+
+    fn update_tags(&self, id: i32, add: &[String], remove: &[String]) -> Result<()> {
+        let trans = self.transaction()?;
+
+        for tag in add {
+            trans.execute("INSERT INTO task_tag(task_id, tag) VALUES ($1, $2) \
+                           ON CONFLICT DO NOTHING",
+                         &[&id, tag])?;
+        }
+        for tag in remove {
+            trans.execute("DELETE FROM task_tag WHERE task_id = $1 AND tag = $2", &[&id, tag])?;
+        }
+        trans.commit()?;
+        Ok(())
+    }
+
+    fn set_parent(&self, id: i32, parent_id: Option<i32>) -> Result<()> {
+        let updated = self.execute("UPDATE task SET parent_id = $2 WHERE id = $1",
+                                   &[&id, &parent_id])?;
+        expect_one_row(updated, "task", id)
+    }
+
+    fn add_dependency(&self, task_id: i32, blocker_id: i32) -> Result<()> {
+        self.execute("INSERT INTO task_dependency(task_id, blocker_id) VALUES ($1, $2) \
+                      ON CONFLICT DO NOTHING",
+                     &[&task_id, &blocker_id])?;
+        Ok(())
+    }
+
+    fn remove_dependency(&self, task_id: i32, blocker_id: i32) -> Result<()> {
+        let updated = self.execute("DELETE FROM task_dependency \
+                                    WHERE task_id = $1 AND blocker_id = $2",
+                                   &[&task_id, &blocker_id])?;
+        if updated == 0 {
+            return Err(Error::not_found("dependency",
+                                        format!("of task {} on task {}", task_id, blocker_id)));
+        }
+        Ok(())
+    }
+
+    fn all_dependencies(&self) -> Result<Vec<Dependency>> {
+        let mut result = vec![];
+        for row in &self.query("SELECT task_id, blocker_id FROM task_dependency \
+                                ORDER BY task_id, blocker_id",
+                               &[])? {
+            result.push(Dependency {
+                            task_id: row.get(0),
+                            blocker_id: row.get(1),
+                        });
+        }
+        Ok(result)
+    }
+
+    fn finish_task(&self, id: i32, date_finished: DateTime<Local>) -> Result<()> {
+        let updated = self.execute("
+UPDATE task SET open = FALSE, date_finished = COALESCE(date_finished, $2)
+WHERE id = $1",
+                                   &[&id, &date_finished])?;
+        expect_one_row(updated, "task", id)
+    }
+
+    fn finish_recurring_task(&self,
+                             id: i32,
+                             date_finished: DateTime<Local>,
+                             next: &Task)
+                             -> Result<Task> {
+        let trans = self.transaction()?;
+
+        let updated = trans.execute("
+UPDATE task SET open = FALSE, date_finished = COALESCE(date_finished, $2)
+WHERE id = $1",
+                                    &[&id, &date_finished])?;
+        expect_one_row(updated, "task", id)?;
+        let next_id = insert_task(&trans, next)?;
+        trans.commit()?;
+        self.find_task(next_id)?.ok_or_else(|| Error::not_found("task", next_id))
+    }
+
+    fn reopen_task(&self, id: i32) -> Result<()> {
+        let updated = self.execute("UPDATE task SET open = TRUE, date_finished = NULL \
+                                    WHERE id = $1",
+                                   &[&id])?;
+        expect_one_row(updated, "task", id)
+    }
+
+    fn all_tasks(&self) -> Result<Vec<Task>> {
+        let mut result = vec![];
+        for row in &self.query(&format!("
+SELECT {}
+FROM task
+ORDER BY date_created DESC",
+                                        TASK_COLUMNS),
+                               &[])? {
+            let r = &mut result;
+            r.push(unpack_task(row));
+        }
+        Ok(result)
+    }
+
+    fn find_task(&self, id: i32) -> Result<Option<Task>> {
+        let rows = &self.query(&format!("
+SELECT {}
+FROM task
+WHERE id = $1",
+                                        TASK_COLUMNS),
+                               &[&id])?;
+
+        if rows.len() != 1 {
+            Ok(None)
+        } else {
+            let row = rows.get(0);
+            Ok(Some(unpack_task(row)))
+        }
+    }
+
+    fn find_task_aux(&self, id: i32) -> Result<Option<TaskAux>> {
+        let rows = &self.query(&task_aux_query("WHERE task.id = $1"), &[&id])?;
+
+        if rows.len() != 1 {
+            Ok(None)
+        } else {
+            Ok(Some(unpack_task_aux(rows.get(0))))
+        }
+    }
+
+    fn all_tasks_aux(&self) -> Result<Vec<TaskAux>> {
+        let mut result = vec![];
+        for row in &self.query(&task_aux_query(""), &[])? {
+            result.push(unpack_task_aux(row));
+        }
+        Ok(result)
+    }
+
+    fn subtree(&self, root: Option<i32>, depth: Option<i32>) -> Result<Vec<TreeNode>> {
+        // ordering by the paths of IDs lists each task right before its subtree
+        let rows = self.query(&format!("
+WITH RECURSIVE tree(id, depth, path) AS (
+    SELECT id, 0, ARRAY[id] FROM task
+    WHERE CASE WHEN $1::INTEGER IS NULL THEN parent_id IS NULL ELSE id = $1 END
+    UNION ALL
+    SELECT task.id, tree.depth + 1, tree.path || task.id
+    FROM tree JOIN task ON task.parent_id = tree.id
+    WHERE ($2::INTEGER IS NULL OR tree.depth < $2) AND task.id <> ALL(tree.path)
+)
+SELECT {}, tree.depth, tree.path
+FROM tree JOIN task ON task.id = tree.id
+ORDER BY tree.path",
+                                       TASK_COLUMNS),
+                              &[&root, &depth])?;
+        Ok(rows.iter()
+               .map(|row| {
+                        TreeNode {
+                            depth: row.get(11),
+                            path: row.get(12),
+                            task: unpack_task(row),
+                        }
+                    })
+               .collect())
+    }
+
+    fn open_leaves(&self, order: TaskOrder) -> Result<Vec<Task>> {
+        let mut result = vec![];
+        for row in &self.query(&format!("
+SELECT {}
+FROM task
+WHERE NOT EXISTS (
+    SELECT t2.id FROM task t2 WHERE task.id = t2.parent_id AND t2.open = TRUE
+    ) AND task.open = TRUE
+ORDER BY {}",
+                                        TASK_COLUMNS,
+                                        order_by(order)),
+                               &[])? {
+            let r = &mut result;
+            r.push(unpack_task(row));
+        }
+        Ok(result)
+    }
+
+    fn find_recently_updated(&self, days: i32) -> Result<Vec<Review>> {
+        let mut result = vec![];
+        for row in &self.query("
+WITH recent_note AS (
+    SELECT * FROM note WHERE note.date_start > now() - interval '1 days' * $1::int
+)
+SELECT task.id, title AS task_title, open, date_finished, recent_note.id AS note_id, recent_note.body AS note_body, recent_note.date_start AS last_updated
+FROM task, recent_note
+WHERE recent_note.task_id = task.id
+UNION ALL
+SELECT task.id, title, open, date_finished, NULL, NULL, date_finished
+FROM task
+WHERE date_finished > now() - interval '1 days' * $1::int
+    AND NOT EXISTS (SELECT 1 FROM recent_note WHERE recent_note.task_id = task.id)
+ORDER BY 1 DESC, 5 DESC, 7 DESC",
+                               &[&days])? {
+            let r = &mut result;
+            r.push(Review {
+                       task_id: row.get(0),
+                       task_title: row.get(1),
+                       open: row.get(2),
+                       date_finished: row.get(3),
+                       note_id: row.get(4),
+                       note_body: row.get(5),
+                       last_updated: row.get(6),
+                   })
+        }
+        Ok(result)
+    }
+
+    fn search(&self, query: &str, filter: &SearchFilter) -> Result<Vec<SearchResult>> {
+        let mut result = vec![];
+        // the to_tsvector() expressions have to match the indexes from migration 4 to use them
+        for row in &self.query("
+WITH q AS (SELECT websearch_to_tsquery('english', $1) AS query)
+SELECT task.id, task.title, task.open, NULL::INTEGER,
+    ts_headline('english', title || ' ' || body, q.query, $5),
+    ts_rank(to_tsvector('english', title || ' ' || body), q.query),
+    task.date_created
+FROM task, q
+WHERE to_tsvector('english', title || ' ' || body) @@ q.query
+    AND ($2::BOOL IS NULL OR task.open = $2)
+    AND ($3::TIMESTAMPTZ IS NULL OR task.date_created >= $3)
+    AND ($4::TIMESTAMPTZ IS NULL OR task.date_created < $4)
+UNION ALL
+SELECT task.id, task.title, task.open, note.id,
+    ts_headline('english', note.body, q.query, $5),
+    ts_rank(to_tsvector('english', note.body), q.query),
+    note.date_start
+FROM note JOIN task ON task.id = note.task_id, q
+WHERE to_tsvector('english', note.body) @@ q.query
+    AND ($2::BOOL IS NULL OR task.open = $2)
+    AND ($3::TIMESTAMPTZ IS NULL OR note.date_start >= $3)
+    AND ($4::TIMESTAMPTZ IS NULL OR note.date_start < $4)
+ORDER BY 6 DESC, 1, 4 NULLS FIRST",
+                               &[&query,
+                                 &filter.open,
+                                 &filter.from,
+                                 &filter.to,
+                                 &"StartSel=*, StopSel=*, MaxWords=20, MinWords=5"])? {
+            result.push(SearchResult {
+                            task_id: row.get(0),
+                            task_title: row.get(1),
+                            open: row.get(2),
+                            note_id: row.get(3),
+                            snippet: row.get(4),
+                            rank: row.get(5),
+                            date: row.get(6),
+                        });
+        }
+        Ok(result)
+    }
+
+    fn create_note(&self,
+                   task_id: i32,
+                   body: &str,
+                   task_body: &str,
+                   date_start: DateTime<Local>,
+                   date_end: DateTime<Local>)
+                   -> Result<Note> {
+        let trans = self.transaction()?;
+
+        let updated = trans.execute("UPDATE task SET body = $2 WHERE id = $1",
+                     &[&task_id, &task_body])?;
+        expect_one_row(updated, "task", task_id)?;
+        let note = {
+            let rows = trans.query("
+INSERT INTO note(task_id, body, date_start, date_end) VALUES ($1, $2, $3, $4)
+RETURNING id, task_id, body, date_start, date_end",
+                                   &[&task_id, &body, &date_start, &date_end])?;
+            unpack_note(rows.get(0))
+        };
+        trans.commit()?;
+        Ok(note)
+    }
+
+    fn update_note(&self, id: i32, body: &str, task_body: &str) -> Result<()> {
+        let trans = self.transaction()?;
+
+        let task_id: i32 = {
+            let rows = trans.query("UPDATE note SET body = $2 WHERE id = $1 RETURNING task_id",
+                                   &[&id, &body])?;
+            if rows.len() != 1 {
+                return Err(Error::not_found("note", id));
+            }
+            rows.get(0).get(0)
+        };
+        trans.execute("UPDATE task SET body = $2 WHERE id = $1",
+                     &[&task_id, &task_body])?;
+        trans.commit()?;
+        Ok(())
+    }
+
+    fn start_timer(&self, task_id: i32, date_start: DateTime<Local>) -> Result<Timer> {
+        self.execute("INSERT INTO timer(task_id, date_start) VALUES ($1, $2)",
+                     &[&task_id, &date_start])?;
+        Ok(Timer {
+               task_id,
+               date_start,
+           })
+    }
+
+    fn active_timer(&self) -> Result<Option<Timer>> {
+        let rows = &self.query("SELECT task_id, date_start FROM timer", &[])?;
+
+        if rows.len() != 1 {
+            Ok(None)
+        } else {
+            let row = rows.get(0);
+            Ok(Some(Timer {
+                        task_id: row.get(0),
+                        date_start: row.get(1),
+                    }))
+        }
+    }
+
+    fn stop_timer(&self,
+                  body: &str,
+                  task_body: &str,
+                  date_end: DateTime<Local>)
+                  -> Result<Note> {
+        let trans = self.transaction()?;
+
+        let (task_id, date_start): (i32, DateTime<Local>) = {
+            let rows = trans.query("DELETE FROM timer RETURNING task_id, date_start", &[])?;
+            if rows.len() != 1 {
+                return Err(Error::Validation("No timer is running".to_owned()));
+            }
+            let row = rows.get(0);
+            (row.get(0), row.get(1))
+        };
+        trans.execute("UPDATE task SET body = $2 WHERE id = $1",
+                     &[&task_id, &task_body])?;
+        let note = {
+            let rows = trans.query("
+INSERT INTO note(task_id, body, date_start, date_end) VALUES ($1, $2, $3, $4)
+RETURNING id, task_id, body, date_start, date_end",
+                                   &[&task_id, &body, &date_start, &date_end])?;
+            unpack_note(rows.get(0))
+        };
+        trans.commit()?;
+        Ok(note)
+    }
+
+    fn delete_note(&self, id: i32) -> Result<()> {
+        let updated = self.execute("DELETE FROM note WHERE id = $1", &[&id])?;
+        expect_one_row(updated, "note", id)
+    }
+
+    fn find_note(&self, id: i32) -> Result<Option<Note>> {
+        let rows = &self.query("SELECT id, task_id, body, date_start, date_end FROM note \
+                                WHERE id = $1",
+                               &[&id])?;
+
+        if rows.len() != 1 {
+            Ok(None)
+        } else {
+            Ok(Some(unpack_note(rows.get(0))))
+        }
+    }
+
+    fn find_notes(&self, task_id: i32) -> Result<Vec<Note>> {
+        let mut result = vec![];
+        for row in &self.query("
+SELECT id, task_id, body, date_start, date_end
+FROM note
+WHERE task_id = $1
+ORDER BY date_start",
+                               &[&task_id])? {
+            let r = &mut result;
+            r.push(unpack_note(row));
+        }
+        Ok(result)
+    }
+
+    fn find_notes_aux(&self, task_id: i32) -> Result<Vec<NoteAux>> {
+        let mut result = vec![];
+        for row in &self.query("
+SELECT id, task_id, body, date_start, date_end, EXTRACT(EPOCH FROM date_end - date_start)::REAL AS duration
+FROM note
+WHERE task_id = $1
+ORDER BY date_start",
+                               &[&task_id])? {
+            let r = &mut result;
+            r.push(NoteAux {
+                       id: row.get(0),
+                       task_id: row.get(1),
+                       body: row.get(2),
+                       date_start: row.get(3),
+                       date_end: row.get(4),
+                       duration_seconds: row.get(5),
+                   });
+        }
+        Ok(result)
+    }
+
+    fn find_notes_between(&self,
+                          from: DateTime<Local>,
+                          to: DateTime<Local>)
+                          -> Result<Vec<NoteAux>> {
+        let mut result = vec![];
+        for row in &self.query("
+SELECT id, task_id, body, date_start, date_end, EXTRACT(EPOCH FROM date_end - date_start)::REAL AS duration
+FROM note
+WHERE date_start >= $1 AND date_start < $2
+ORDER BY task_id, date_start",
+                               &[&from, &to])? {
+            let r = &mut result;
+            r.push(NoteAux {
+                       id: row.get(0),
+                       task_id: row.get(1),
+                       body: row.get(2),
+                       date_start: row.get(3),
+                       date_end: row.get(4),
+                       duration_seconds: row.get(5),
+                   });
+        }
+        Ok(result)
+    }
+
+    fn latest_note_dates(&self) -> Result<Vec<(i32, DateTime<Local>)>> {
+        let mut result = vec![];
+        for row in &self.query("SELECT task_id, MAX(date_start) FROM note \
+                                GROUP BY task_id ORDER BY task_id",
+                               &[])? {
+            result.push((row.get(0), row.get(1)));
+        }
+        Ok(result)
+    }
+
+    fn create_series(&self, task_id: i32, recurrence: &str, body: &str) -> Result<Series> {
+        let trans = self.transaction()?;
+
+        let series = {
+            let rows = trans.query("INSERT INTO task_series(recurrence, body) VALUES ($1, $2) \
+                                    RETURNING id, recurrence, body",
+                                   &[&recurrence, &body])?;
+            unpack_series(rows.get(0))
+        };
+        let updated = trans.execute("UPDATE task SET series_id = $2 WHERE id = $1",
+                                    &[&task_id, &series.id])?;
+        expect_one_row(updated, "task", task_id)?;
+        trans.commit()?;
+        Ok(series)
+    }
+
+    fn set_series(&self, task_id: i32, series_id: Option<i32>) -> Result<()> {
+        let updated = self.execute("UPDATE task SET series_id = $2 WHERE id = $1",
+                                   &[&task_id, &series_id])?;
+        expect_one_row(updated, "task", task_id)
+    }
+
+    fn find_series(&self, id: i32) -> Result<Option<Series>> {
+        let rows = self.query("SELECT id, recurrence, body FROM task_series WHERE id = $1",
+                              &[&id])?;
+        Ok(rows.iter().next().map(unpack_series))
+    }
+
+    fn all_series(&self) -> Result<Vec<Series>> {
+        let rows = self.query("SELECT id, recurrence, body FROM task_series ORDER BY id", &[])?;
+        Ok(rows.iter().map(unpack_series).collect())
+    }
+
+    fn upsert_template(&self, name: &str, body: &str) -> Result<()> {
+        self.execute("INSERT INTO template(name, body) VALUES ($1, $2) ON CONFLICT (name) DO \
+                      UPDATE SET BODY = $2",
+                     &[&name, &body])?;
+        Ok(())
+    }
+
+    fn find_template(&self, name: &str) -> Result<Option<String>> {
+        let rows = &self.query("SELECT body FROM template WHERE name = $1", &[&name])?;
+
+        if rows.len() != 1 {
+            Ok(None)
+        } else {
+            let row = rows.get(0);
+            Ok(Some(row.get(0)))
+        }
+    }
+
+    fn all_templates(&self) -> Result<Vec<Template>> {
+        let mut result = vec![];
+        for row in &self.query("SELECT name, body FROM template ORDER BY name", &[])? {
+            let r = &mut result;
+            r.push(Template {
+                       name: row.get(0),
+                       body: row.get(1),
+                   });
+        }
+        Ok(result)
+    }
+}
@@ -0,0 +1,938 @@
+use std::path::Path;
+
+use chrono::*;
+use rusqlite::{self, Connection, OptionalExtension, Row};
+use rusqlite::types::Type;
+
+use error::{Error, Result};
+use migrations::{Direction, Migration};
+use models::{Task, TaskAux, Note, NoteAux, Template, Review, Timer, SearchFilter,
+             SearchResult, TaskOrder, Dependency, Series, TreeNode};
+use store::{Store, split_tags, order_by};
+use store::text_search;
+
+/// An embedded, single-file store.
+///
+/// Timestamps are kept as UTC RFC 3339 text with a fixed number of fractional digits so that they
+/// sort and compare lexically, and durations are computed with `julianday()`.
+pub struct SqliteStore {
+    conn: Connection,
+}
+
+impl SqliteStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> rusqlite::Result<SqliteStore> {
+        SqliteStore::init(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> rusqlite::Result<SqliteStore> {
+        SqliteStore::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> rusqlite::Result<SqliteStore> {
+        // ON DELETE CASCADE is a no-op unless foreign keys are enabled on every connection
+        conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+        Ok(SqliteStore { conn })
+    }
+}
+
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "create task, note and template tables",
+        up: "
+CREATE TABLE IF NOT EXISTS task (
+    id              INTEGER PRIMARY KEY AUTOINCREMENT,
+    parent_id       INTEGER REFERENCES task(id) ON DELETE CASCADE,
+    title           TEXT NOT NULL,
+    body            TEXT NOT NULL,
+    open            BOOLEAN NOT NULL DEFAULT TRUE,
+    date_created    TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS note (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    task_id     INTEGER REFERENCES task(id) ON DELETE CASCADE,
+    body        TEXT NOT NULL,
+    date_start  TEXT NOT NULL,
+    date_end    TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS template (
+    name    TEXT PRIMARY KEY,
+    body    TEXT NOT NULL
+);
+",
+        down: "
+DROP TABLE IF EXISTS note;
+DROP TABLE IF EXISTS task;
+DROP TABLE IF EXISTS template;
+",
+    },
+    Migration {
+        version: 2,
+        name: "add task.date_finished",
+        up: "ALTER TABLE task ADD COLUMN date_finished TEXT;",
+        down: "ALTER TABLE task DROP COLUMN date_finished;",
+    },
+    Migration {
+        version: 3,
+        name: "create timer table",
+        up: "
+CREATE TABLE timer (
+    id          INTEGER PRIMARY KEY CHECK (id = 1),
+    task_id     INTEGER NOT NULL REFERENCES task(id) ON DELETE CASCADE,
+    date_start  TEXT NOT NULL
+);
+",
+        down: "DROP TABLE timer;",
+    },
+    // Postgres adds full-text search indexes here; searches in SQLite use LIKE instead
+    Migration {
+        version: 4,
+        name: "add full-text search indexes (Postgres only)",
+        up: "",
+        down: "",
+    },
+    Migration {
+        version: 5,
+        name: "create task_tag table",
+        up: "
+CREATE TABLE task_tag (
+    task_id     INTEGER NOT NULL REFERENCES task(id) ON DELETE CASCADE,
+    tag         TEXT NOT NULL,
+    PRIMARY KEY (task_id, tag)
+);
+",
+        down: "DROP TABLE task_tag;",
+    },
+    Migration {
+        version: 6,
+        name: "add task.priority and task.due_date",
+        up: "
+ALTER TABLE task ADD COLUMN priority INTEGER;
+ALTER TABLE task ADD COLUMN due_date TEXT;
+",
+        down: "
+ALTER TABLE task DROP COLUMN priority;
+ALTER TABLE task DROP COLUMN due_date;
+",
+    },
+    Migration {
+        version: 7,
+        name: "create task_dependency table",
+        up: "
+CREATE TABLE task_dependency (
+    task_id     INTEGER NOT NULL REFERENCES task(id) ON DELETE CASCADE,
+    blocker_id  INTEGER NOT NULL REFERENCES task(id) ON DELETE CASCADE,
+    PRIMARY KEY (task_id, blocker_id),
+    CHECK (task_id <> blocker_id)
+);
+",
+        down: "DROP TABLE task_dependency;",
+    },
+    Migration {
+        version: 8,
+        name: "create task_series table and add task.series_id",
+        up: "
+CREATE TABLE task_series (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    recurrence  TEXT NOT NULL,
+    body        TEXT NOT NULL
+);
+
+ALTER TABLE task ADD COLUMN series_id INTEGER REFERENCES task_series(id) ON DELETE SET NULL;
+",
+        down: "
+ALTER TABLE task DROP COLUMN series_id;
+DROP TABLE task_series;
+",
+    },
+];
+
+fn format_timestamp(date: &DateTime<Local>) -> String {
+    date.with_timezone(&UTC).format("%Y-%m-%dT%H:%M:%S%.6fZ").to_string()
+}
+
+fn parse_timestamp(text: &str) -> ParseResult<DateTime<Local>> {
+    DateTime::parse_from_rfc3339(text).map(|date| date.with_timezone(&Local))
+}
+#[test]
+fn test_timestamp_roundtrip() {
+    let date = Local.ymd(2017, 3, 9).and_hms_micro(23, 5, 1, 250);
+    let text = format_timestamp(&date);
+    assert_eq!(parse_timestamp(&text).unwrap(), date);
+    assert!(text < format_timestamp(&(date + Duration::seconds(1))));
+}
+
+fn get_timestamp(row: &Row, idx: usize) -> rusqlite::Result<DateTime<Local>> {
+    let text: String = row.get(idx)?;
+    parse_timestamp(&text)
+        .map_err(|err| rusqlite::Error::FromSqlConversionFailure(idx, Type::Text, Box::new(err)))
+}
+
+fn get_optional_timestamp(row: &Row, idx: usize) -> rusqlite::Result<Option<DateTime<Local>>> {
+    let text: Option<String> = row.get(idx)?;
+    match text {
+        Some(_) => get_timestamp(row, idx).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Dates without a time are kept as YYYY-MM-DD text.
+fn format_day(date: &NaiveDate) -> String {
+    date.format("%Y-%m-%d").to_string()
+}
+
+fn get_optional_day(row: &Row, idx: usize) -> rusqlite::Result<Option<NaiveDate>> {
+    let text: Option<String> = row.get(idx)?;
+    match text {
+        Some(text) => {
+            NaiveDate::parse_from_str(&text, "%Y-%m-%d").map(Some).map_err(|err| {
+                rusqlite::Error::FromSqlConversionFailure(idx, Type::Text, Box::new(err))
+            })
+        }
+        None => Ok(None),
+    }
+}
+
+/// The columns `unpack_task` expects, in order
+const TASK_COLUMNS: &str = "task.id, task.parent_id, task.title, task.body, task.open, \
+                            task.date_created, task.date_finished, task.priority, \
+                            task.due_date, task.series_id, (
+    SELECT group_concat(tag, ' ') FROM (
+        SELECT tag FROM task_tag WHERE task_tag.task_id = task.id ORDER BY tag
+        )
+    )";
+
+fn unpack_task(row: &Row) -> rusqlite::Result<Task> {
+    Ok(Task {
+           id: row.get(0)?,
+           parent_id: row.get(1)?,
+           title: row.get(2)?,
+           body: row.get(3)?,
+           open: row.get(4)?,
+           date_created: get_timestamp(row, 5)?,
+           date_finished: get_optional_timestamp(row, 6)?,
+           priority: row.get(7)?,
+           due_date: get_optional_day(row, 8)?,
+           series_id: row.get(9)?,
+           tags: split_tags(row.get(10)?),
+       })
+}
+
+fn unpack_task_aux(row: &Row) -> rusqlite::Result<TaskAux> {
+    let duration: f64 = row.get(11)?;
+    let total_duration: f64 = row.get(12)?;
+    Ok(TaskAux {
+           id: row.get(0)?,
+           parent_id: row.get(1)?,
+           title: row.get(2)?,
+           body: row.get(3)?,
+           open: row.get(4)?,
+           date_created: get_timestamp(row, 5)?,
+           date_finished: get_optional_timestamp(row, 6)?,
+           priority: row.get(7)?,
+           due_date: get_optional_day(row, 8)?,
+           series_id: row.get(9)?,
+           tags: split_tags(row.get(10)?),
+           duration_seconds: duration as f32,
+           total_duration_seconds: total_duration as f32,
+       })
+}
+
+/// Selects the tasks matching `filter` (a `WHERE` clause, or nothing for every task) with their
+/// own durations and the durations of their whole subtrees, for `unpack_task_aux`.
+fn task_aux_query(filter: &str) -> String {
+    format!("
+WITH RECURSIVE subtree(root_id, id) AS (
+    SELECT task.id, task.id FROM task {filter}
+    UNION ALL
+    SELECT subtree.root_id, task.id FROM subtree JOIN task ON task.parent_id = subtree.id
+), own AS (
+    SELECT task_id, SUM((julianday(date_end) - julianday(date_start)) * 86400.0) AS seconds
+    FROM note GROUP BY task_id
+)
+SELECT {columns},
+    COALESCE((SELECT seconds FROM own WHERE own.task_id = task.id), 0.0),
+    COALESCE((SELECT SUM(own.seconds) FROM subtree JOIN own ON own.task_id = subtree.id
+              WHERE subtree.root_id = task.id), 0.0)
+FROM task {filter}
+ORDER BY date_created DESC",
+            filter = filter,
+            columns = TASK_COLUMNS)
+}
+#[test]
+fn test_task_aux_query() {
+    use migrations::migrate;
+
+    let store = SqliteStore::open_in_memory().unwrap();
+    migrate(&store, None).unwrap();
+    let now = Local::now();
+    Task::new(None, "Root", "", now).create(&store).unwrap();
+    Task::new(Some(1), "Child", "", now).create(&store).unwrap();
+    Task::new(Some(2), "Grandchild", "", now).create(&store).unwrap();
+    Note::create(&store, 2, "a", "", now, now + Duration::minutes(30)).unwrap();
+    Note::create(&store, 3, "b", "", now, now + Duration::hours(1)).unwrap();
+
+    let root = Task::find_aux(&store, 1).unwrap();
+    assert_eq!((root.duration_seconds, root.total_duration_seconds), (0f32, 5400f32));
+    let child = Task::find_aux(&store, 2).unwrap();
+    assert_eq!((child.duration_seconds, child.total_duration_seconds), (1800f32, 5400f32));
+    assert_eq!(Task::all_aux(&store).unwrap().len(), 3);
+}
+#[test]
+fn test_subtree() {
+    use migrations::migrate;
+
+    // IDs 9 and 10 would swap places if the paths were not zero-padded
+    let store = SqliteStore::open_in_memory().unwrap();
+    migrate(&store, None).unwrap();
+    let now = Local::now();
+    Task::new(None, "Root", "", now).create(&store).unwrap();
+    for _ in 2..11 {
+        Task::new(Some(1), "Child", "", now).create(&store).unwrap();
+    }
+    Task::new(Some(10), "Grandchild", "", now).create(&store).unwrap();
+    Task::new(Some(9), "Grandchild", "", now).create(&store).unwrap();
+
+    let paths: Vec<Vec<i32>> = Task::subtree(&store, None, None)
+        .unwrap()
+        .into_iter()
+        .skip(9)
+        .map(|node| node.path)
+        .collect();
+    assert_eq!(paths, vec![vec![1, 9, 12], vec![1, 10], vec![1, 10, 11]]);
+    let ids = |root, depth| -> Vec<i32> {
+        Task::subtree(&store, root, depth).unwrap().iter().map(|node| node.task.id).collect()
+    };
+    assert_eq!(ids(None, None), vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 12, 10, 11]);
+    assert_eq!(ids(None, Some(1)), vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10]);
+    assert_eq!(ids(None, Some(0)), vec![1]);
+    assert!(Task::subtree(&store, Some(42), None).is_err());
+}
+
+/// Inserts the task with its tags, returning its new ID.
+fn insert_task(conn: &Connection, task: &Task) -> Result<i32> {
+    let id: i32 = conn.query_row("
+INSERT INTO task(parent_id, title, body, date_created, priority, due_date, series_id)
+VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+RETURNING id",
+                   params![task.parent_id,
+                           task.title,
+                           task.body,
+                           format_timestamp(&task.date_created),
+                           task.priority,
+                           task.due_date.as_ref().map(format_day),
+                           task.series_id],
+                   |row| row.get(0))?;
+    for tag in &task.tags {
+        conn.execute("INSERT INTO task_tag(task_id, tag) VALUES (?1, ?2)", params![id, tag])?;
+    }
+    Ok(id)
+}
+
+fn unpack_series(row: &Row) -> rusqlite::Result<Series> {
+    Ok(Series {
+           id: row.get(0)?,
+           recurrence: row.get(1)?,
+           body: row.get(2)?,
+       })
+}
+
+fn unpack_note(row: &Row) -> rusqlite::Result<Note> {
+    Ok(Note {
+           id: row.get(0)?,
+           task_id: row.get(1)?,
+           body: row.get(2)?,
+           date_start: get_timestamp(row, 3)?,
+           date_end: get_timestamp(row, 4)?,
+       })
+}
+
+fn unpack_note_aux(row: &Row) -> rusqlite::Result<NoteAux> {
+    let duration: f64 = row.get(5)?;
+    Ok(NoteAux {
+           id: row.get(0)?,
+           task_id: row.get(1)?,
+           body: row.get(2)?,
+           date_start: get_timestamp(row, 3)?,
+           date_end: get_timestamp(row, 4)?,
+           duration_seconds: duration as f32,
+       })
+}
+
+fn expect_one_row(updated: usize, kind: &str, id: i32) -> Result<()> {
+    if updated == 0 {
+        Err(Error::not_found(kind, id))
+    } else {
+        Ok(())
+    }
+}
+
+/// `LIKE` conditions on `expression`, one per word of `query`, with the patterns to bind to them.
+fn like_conditions(expression: &str, query: &str) -> (String, Vec<String>) {
+    let mut conditions = vec![];
+    let mut patterns = vec![];
+    for (i, word) in query.split_whitespace().enumerate() {
+        conditions.push(format!("{} LIKE ?{} ESCAPE '\\'", expression, i + 1));
+        let word = word.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+        patterns.push(format!("%{}%", word));
+    }
+    (conditions.join(" AND "), patterns)
+}
+
+impl SqliteStore {
+    fn query_tasks(&self, sql: &str, params: &[&dyn rusqlite::ToSql]) -> Result<Vec<Task>> {
+        let mut stmt = self.conn.prepare(sql)?;
+        let rows = stmt.query_map(params, unpack_task)?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+}
+
+impl Store for SqliteStore {
+    fn migrations(&self) -> &'static [Migration] {
+        MIGRATIONS
+    }
+
+    fn applied_migrations(&self) -> Result<Vec<i32>> {
+        let exists: bool = self.conn
+            .query_row("SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name \
+                        = 'schema_version')",
+                       params![],
+                       |row| row.get(0))?;
+        if !exists {
+            return Ok(vec![]);
+        }
+
+        let mut stmt = self.conn.prepare("SELECT version FROM schema_version ORDER BY version")?;
+        let rows = stmt.query_map(params![], |row| row.get(0))?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn apply_migration(&self, migration: &Migration, direction: Direction) -> Result<()> {
+        let trans = self.conn.unchecked_transaction()?;
+
+        trans.execute_batch("
+CREATE TABLE IF NOT EXISTS schema_version (
+    version         INTEGER PRIMARY KEY,
+    name            TEXT NOT NULL,
+    date_applied    TEXT NOT NULL
+);
+")?;
+        match direction {
+            Direction::Up => {
+                trans.execute_batch(migration.up)?;
+                trans.execute("INSERT INTO schema_version(version, name, date_applied) VALUES (?1, \
+                               ?2, ?3)",
+                             params![migration.version,
+                                     migration.name,
+                                     format_timestamp(&Local::now())])?;
+            }
+            Direction::Down => {
+                trans.execute_batch(migration.down)?;
+                trans.execute("DELETE FROM schema_version WHERE version = ?1",
+                             params![migration.version])?;
+            }
+        }
+        trans.commit()?;
+        Ok(())
+    }
+
+    fn create_task(&self, task: &Task) -> Result<Task> {
+        let trans = self.conn.unchecked_transaction()?;
+
+        let id = insert_task(&trans, task)?;
+        trans.commit()?;
+        self.find_task(id)?.ok_or_else(|| Error::not_found("task", id))
+    }
+
+    fn delete_task(&self, id: i32) -> Result<()> {
+        let updated = self.conn.execute("DELETE FROM task WHERE id = ?1", params![id])?;
+        expect_one_row(updated, "task", id)
+    }
+
+    fn update_task(&self, id: i32, title: &str, body: &str) -> Result<()> {
+        let updated = self.conn
+            .execute("UPDATE task SET title = ?2, body = ?3 WHERE id = ?1",
+                     params![id, title, body])?;
+        expect_one_row(updated, "task", id)
+    }
+
+    fn update_tags(&self, id: i32, add: &[String], remove: &[String]) -> Result<()> {
+        let trans = self.conn.unchecked_transaction()?;
+
+        for tag in add {
+            trans.execute("INSERT OR IGNORE INTO task_tag(task_id, tag) VALUES (?1, ?2)",
+                         params![id, tag])?;
+        }
+        for tag in remove {
+            trans.execute("DELETE FROM task_tag WHERE task_id = ?1 AND tag = ?2",
+                         params![id, tag])?;
+        }
+        trans.commit()?;
+        Ok(())
+    }
+
+    fn set_parent(&self, id: i32, parent_id: Option<i32>) -> Result<()> {
+        let updated = self.conn
+            .execute("UPDATE task SET parent_id = ?2 WHERE id = ?1",
+                     params![id, parent_id])?;
+        expect_one_row(updated, "task", id)
+    }
+
+    fn add_dependency(&self, task_id: i32, blocker_id: i32) -> Result<()> {
+        self.conn
+            .execute("INSERT OR IGNORE INTO task_dependency(task_id, blocker_id) VALUES (?1, ?2)",
+                     params![task_id, blocker_id])?;
+        Ok(())
+    }
+
+    fn remove_dependency(&self, task_id: i32, blocker_id: i32) -> Result<()> {
+        let updated = self.conn
+            .execute("DELETE FROM task_dependency WHERE task_id = ?1 AND blocker_id = ?2",
+                     params![task_id, blocker_id])?;
+        if updated == 0 {
+            return Err(Error::not_found("dependency",
+                                        format!("of task {} on task {}", task_id, blocker_id)));
+        }
+        Ok(())
+    }
+
+    fn all_dependencies(&self) -> Result<Vec<Dependency>> {
+        let mut stmt = self.conn
+            .prepare("SELECT task_id, blocker_id FROM task_dependency \
+                      ORDER BY task_id, blocker_id")?;
+        let rows = stmt.query_map(params![], |row| {
+                Ok(Dependency {
+                       task_id: row.get(0)?,
+                       blocker_id: row.get(1)?,
+                   })
+            })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn finish_task(&self, id: i32, date_finished: DateTime<Local>) -> Result<()> {
+        let updated = self.conn
+            .execute("
+UPDATE task SET open = FALSE, date_finished = COALESCE(date_finished, ?2)
+WHERE id = ?1",
+                     params![id, format_timestamp(&date_finished)])?;
+        expect_one_row(updated, "task", id)
+    }
+
+    fn finish_recurring_task(&self,
+                             id: i32,
+                             date_finished: DateTime<Local>,
+                             next: &Task)
+                             -> Result<Task> {
+        let trans = self.conn.unchecked_transaction()?;
+
+        let updated = trans.execute("
+UPDATE task SET open = FALSE, date_finished = COALESCE(date_finished, ?2)
+WHERE id = ?1",
+                     params![id, format_timestamp(&date_finished)])?;
+        expect_one_row(updated, "task", id)?;
+        let next_id = insert_task(&trans, next)?;
+        trans.commit()?;
+        self.find_task(next_id)?.ok_or_else(|| Error::not_found("task", next_id))
+    }
+
+    fn reopen_task(&self, id: i32) -> Result<()> {
+        let updated = self.conn
+            .execute("UPDATE task SET open = TRUE, date_finished = NULL WHERE id = ?1",
+                     params![id])?;
+        expect_one_row(updated, "task", id)
+    }
+
+    fn all_tasks(&self) -> Result<Vec<Task>> {
+        self.query_tasks(&format!("
+SELECT {}
+FROM task
+ORDER BY date_created DESC",
+                                  TASK_COLUMNS),
+                         &[])
+    }
+
+    fn find_task(&self, id: i32) -> Result<Option<Task>> {
+        Ok(self.query_tasks(&format!("
+SELECT {}
+FROM task
+WHERE id = ?1",
+                                     TASK_COLUMNS),
+                            &[&id])?
+            .pop())
+    }
+
+    fn find_task_aux(&self, id: i32) -> Result<Option<TaskAux>> {
+        let task = self.conn
+            .query_row(&task_aux_query("WHERE task.id = ?1"),
+                       params![id],
+                       unpack_task_aux)
+            .optional()?;
+        Ok(task)
+    }
+
+    fn all_tasks_aux(&self) -> Result<Vec<TaskAux>> {
+        let mut stmt = self.conn.prepare(&task_aux_query(""))?;
+        let rows = stmt.query_map(params![], unpack_task_aux)?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn subtree(&self, root: Option<i32>, depth: Option<i32>) -> Result<Vec<TreeNode>> {
+        // paths are zero-padded IDs joined by '/', so that ordering by them lists each task right
+        // before its subtree
+        let mut stmt = self.conn
+            .prepare(&format!("
+WITH RECURSIVE tree(id, depth, path) AS (
+    SELECT id, 0, printf('%010d', id) FROM task
+    WHERE CASE WHEN ?1 IS NULL THEN parent_id IS NULL ELSE id = ?1 END
+    UNION ALL
+    SELECT task.id, tree.depth + 1, tree.path || '/' || printf('%010d', task.id)
+    FROM tree JOIN task ON task.parent_id = tree.id
+    WHERE (?2 IS NULL OR tree.depth < ?2)
+        AND instr('/' || tree.path || '/', '/' || printf('%010d', task.id) || '/') = 0
+)
+SELECT {}, tree.depth, tree.path
+FROM tree JOIN task ON task.id = tree.id
+ORDER BY tree.path",
+                              TASK_COLUMNS))?;
+        let rows = stmt.query_map(params![root, depth], |row| {
+                let path: String = row.get(12)?;
+                let path = path.split('/')
+                    .map(|id| id.parse::<i32>())
+                    .collect::<::std::result::Result<_, _>>()
+                    .map_err(|err| {
+                        rusqlite::Error::FromSqlConversionFailure(12, Type::Text, Box::new(err))
+                    })?;
+                Ok(TreeNode {
+                       task: unpack_task(row)?,
+                       depth: row.get(11)?,
+                       path,
+                   })
+            })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn open_leaves(&self, order: TaskOrder) -> Result<Vec<Task>> {
+        self.query_tasks(&format!("
+SELECT {}
+FROM task
+WHERE NOT EXISTS (
+    SELECT t2.id FROM task t2 WHERE task.id = t2.parent_id AND t2.open = TRUE
+    ) AND task.open = TRUE
+ORDER BY {}",
+                                  TASK_COLUMNS,
+                                  order_by(order)),
+                         &[])
+    }
+
+    fn find_recently_updated(&self, days: i32) -> Result<Vec<Review>> {
+        let since = format_timestamp(&(Local::now() - Duration::days(days as i64)));
+        let mut stmt = self.conn
+            .prepare("
+WITH recent_note AS (
+    SELECT * FROM note WHERE note.date_start > ?1
+)
+SELECT task.id, title AS task_title, open, date_finished, recent_note.id AS note_id, recent_note.body AS note_body, recent_note.date_start AS last_updated
+FROM task, recent_note
+WHERE recent_note.task_id = task.id
+UNION ALL
+SELECT task.id, title, open, date_finished, NULL, NULL, date_finished
+FROM task
+WHERE date_finished > ?1
+    AND NOT EXISTS (SELECT 1 FROM recent_note WHERE recent_note.task_id = task.id)
+ORDER BY 1 DESC, 5 DESC, 7 DESC")?;
+        let rows = stmt.query_map(params![since], |row| {
+                Ok(Review {
+                       task_id: row.get(0)?,
+                       task_title: row.get(1)?,
+                       open: row.get(2)?,
+                       date_finished: get_optional_timestamp(row, 3)?,
+                       note_id: row.get(4)?,
+                       note_body: row.get(5)?,
+                       last_updated: get_timestamp(row, 6)?,
+                   })
+            })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn search(&self, query: &str, filter: &SearchFilter) -> Result<Vec<SearchResult>> {
+        let terms = text_search::terms(query);
+        let mut result = vec![];
+
+        let (conditions, patterns) = like_conditions("(task.title || ' ' || task.body)", query);
+        let params: Vec<&dyn rusqlite::ToSql> =
+            patterns.iter().map(|p| p as &dyn rusqlite::ToSql).collect();
+        let tasks = self.query_tasks(&format!("SELECT {} FROM task WHERE {}",
+                                              TASK_COLUMNS,
+                                              conditions),
+                                     &params)?;
+        for task in tasks {
+            let text = format!("{} {}", task.title, task.body);
+            if let Some(rank) = text_search::rank(&text, &terms) {
+                if filter.matches(task.open, task.date_created) {
+                    result.push(SearchResult {
+                                    task_id: task.id,
+                                    task_title: task.title.clone(),
+                                    open: task.open,
+                                    note_id: None,
+                                    snippet: text_search::snippet(&text, &terms),
+                                    rank,
+                                    date: task.date_created,
+                                });
+                }
+            }
+        }
+
+        let (conditions, patterns) = like_conditions("note.body", query);
+        let params: Vec<&dyn rusqlite::ToSql> =
+            patterns.iter().map(|p| p as &dyn rusqlite::ToSql).collect();
+        let mut stmt = self.conn
+            .prepare(&format!("
+SELECT note.id, note.task_id, note.body, note.date_start, note.date_end, task.title, task.open
+FROM note JOIN task ON task.id = note.task_id
+WHERE {}",
+                              conditions))?;
+        let rows = stmt.query_map(&params[..], |row| {
+                Ok((unpack_note(row)?, row.get::<_, String>(5)?, row.get::<_, bool>(6)?))
+            })?;
+        for row in rows {
+            let (note, task_title, open) = row?;
+            if let Some(rank) = text_search::rank(&note.body, &terms) {
+                if filter.matches(open, note.date_start) {
+                    result.push(SearchResult {
+                                    task_id: note.task_id,
+                                    task_title,
+                                    open,
+                                    note_id: Some(note.id),
+                                    snippet: text_search::snippet(&note.body, &terms),
+                                    rank,
+                                    date: note.date_start,
+                                });
+                }
+            }
+        }
+        text_search::sort_results(&mut result);
+        Ok(result)
+    }
+
+    fn create_note(&self,
+                   task_id: i32,
+                   body: &str,
+                   task_body: &str,
+                   date_start: DateTime<Local>,
+                   date_end: DateTime<Local>)
+                   -> Result<Note> {
+        let trans = self.conn.unchecked_transaction()?;
+
+        let updated = trans.execute("UPDATE task SET body = ?2 WHERE id = ?1",
+                     params![task_id, task_body])?;
+        expect_one_row(updated, "task", task_id)?;
+        let note = trans.query_row("
+INSERT INTO note(task_id, body, date_start, date_end) VALUES (?1, ?2, ?3, ?4)
+RETURNING id, task_id, body, date_start, date_end",
+                       params![task_id,
+                               body,
+                               format_timestamp(&date_start),
+                               format_timestamp(&date_end)],
+                       unpack_note)?;
+        trans.commit()?;
+        Ok(note)
+    }
+
+    fn update_note(&self, id: i32, body: &str, task_body: &str) -> Result<()> {
+        let trans = self.conn.unchecked_transaction()?;
+
+        let task_id: i32 = trans.query_row("UPDATE note SET body = ?2 WHERE id = ?1 \
+                                            RETURNING task_id",
+                       params![id, body],
+                       |row| row.get(0))
+            .optional()?
+            .ok_or_else(|| Error::not_found("note", id))?;
+        trans.execute("UPDATE task SET body = ?2 WHERE id = ?1",
+                     params![task_id, task_body])?;
+        trans.commit()?;
+        Ok(())
+    }
+
+    fn start_timer(&self, task_id: i32, date_start: DateTime<Local>) -> Result<Timer> {
+        self.conn
+            .execute("INSERT INTO timer(id, task_id, date_start) VALUES (1, ?1, ?2)",
+                     params![task_id, format_timestamp(&date_start)])?;
+        Ok(Timer {
+               task_id,
+               date_start,
+           })
+    }
+
+    fn active_timer(&self) -> Result<Option<Timer>> {
+        let timer = self.conn
+            .query_row("SELECT task_id, date_start FROM timer",
+                       params![],
+                       |row| {
+                Ok(Timer {
+                       task_id: row.get(0)?,
+                       date_start: get_timestamp(row, 1)?,
+                   })
+            })
+            .optional()?;
+        Ok(timer)
+    }
+
+    fn stop_timer(&self,
+                  body: &str,
+                  task_body: &str,
+                  date_end: DateTime<Local>)
+                  -> Result<Note> {
+        let trans = self.conn.unchecked_transaction()?;
+
+        let (task_id, date_start): (i32, String) = trans.query_row("DELETE FROM timer \
+                                                                     RETURNING task_id, \
+                                                                     date_start",
+                       params![],
+                       |row| Ok((row.get(0)?, row.get(1)?)))
+            .optional()?
+            .ok_or_else(|| Error::Validation("No timer is running".to_owned()))?;
+        trans.execute("UPDATE task SET body = ?2 WHERE id = ?1",
+                     params![task_id, task_body])?;
+        let note = trans.query_row("
+INSERT INTO note(task_id, body, date_start, date_end) VALUES (?1, ?2, ?3, ?4)
+RETURNING id, task_id, body, date_start, date_end",
+                       params![task_id, body, date_start, format_timestamp(&date_end)],
+                       unpack_note)?;
+        trans.commit()?;
+        Ok(note)
+    }
+
+    fn delete_note(&self, id: i32) -> Result<()> {
+        let updated = self.conn.execute("DELETE FROM note WHERE id = ?1", params![id])?;
+        expect_one_row(updated, "note", id)
+    }
+
+    fn find_note(&self, id: i32) -> Result<Option<Note>> {
+        let note = self.conn
+            .query_row("SELECT id, task_id, body, date_start, date_end FROM note WHERE id = ?1",
+                       params![id],
+                       unpack_note)
+            .optional()?;
+        Ok(note)
+    }
+
+    fn find_notes(&self, task_id: i32) -> Result<Vec<Note>> {
+        let notes = self.find_notes_aux(task_id)?
+            .into_iter()
+            .map(|note| {
+                Note {
+                    id: note.id,
+                    task_id: note.task_id,
+                    body: note.body,
+                    date_start: note.date_start,
+                    date_end: note.date_end,
+                }
+            })
+            .collect();
+        Ok(notes)
+    }
+
+    fn find_notes_aux(&self, task_id: i32) -> Result<Vec<NoteAux>> {
+        let mut stmt = self.conn
+            .prepare("
+SELECT id, task_id, body, date_start, date_end,
+    (julianday(date_end) - julianday(date_start)) * 86400.0 AS duration
+FROM note
+WHERE task_id = ?1
+ORDER BY date_start")?;
+        let rows = stmt.query_map(params![task_id], unpack_note_aux)?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn find_notes_between(&self,
+                          from: DateTime<Local>,
+                          to: DateTime<Local>)
+                          -> Result<Vec<NoteAux>> {
+        let mut stmt = self.conn
+            .prepare("
+SELECT id, task_id, body, date_start, date_end,
+    (julianday(date_end) - julianday(date_start)) * 86400.0 AS duration
+FROM note
+WHERE date_start >= ?1 AND date_start < ?2
+ORDER BY task_id, date_start")?;
+        let rows = stmt.query_map(params![format_timestamp(&from), format_timestamp(&to)],
+                       unpack_note_aux)?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn latest_note_dates(&self) -> Result<Vec<(i32, DateTime<Local>)>> {
+        // timestamps are stored in UTC with a fixed width, so they sort as text
+        let mut stmt = self.conn
+            .prepare("SELECT task_id, MAX(date_start) FROM note \
+                      GROUP BY task_id ORDER BY task_id")?;
+        let rows = stmt.query_map(params![], |row| Ok((row.get(0)?, get_timestamp(row, 1)?)))?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn create_series(&self, task_id: i32, recurrence: &str, body: &str) -> Result<Series> {
+        let trans = self.conn.unchecked_transaction()?;
+
+        let series = trans.query_row("INSERT INTO task_series(recurrence, body) VALUES (?1, ?2) \
+                                      RETURNING id, recurrence, body",
+                       params![recurrence, body],
+                       unpack_series)?;
+        let updated = trans.execute("UPDATE task SET series_id = ?2 WHERE id = ?1",
+                     params![task_id, series.id])?;
+        expect_one_row(updated, "task", task_id)?;
+        trans.commit()?;
+        Ok(series)
+    }
+
+    fn set_series(&self, task_id: i32, series_id: Option<i32>) -> Result<()> {
+        let updated = self.conn
+            .execute("UPDATE task SET series_id = ?2 WHERE id = ?1",
+                     params![task_id, series_id])?;
+        expect_one_row(updated, "task", task_id)
+    }
+
+    fn find_series(&self, id: i32) -> Result<Option<Series>> {
+        let series = self.conn
+            .query_row("SELECT id, recurrence, body FROM task_series WHERE id = ?1",
+                       params![id],
+                       unpack_series)
+            .optional()?;
+        Ok(series)
+    }
+
+    fn all_series(&self) -> Result<Vec<Series>> {
+        let mut stmt = self.conn
+            .prepare("SELECT id, recurrence, body FROM task_series ORDER BY id")?;
+        let rows = stmt.query_map(params![], unpack_series)?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn upsert_template(&self, name: &str, body: &str) -> Result<()> {
+        self.conn
+            .execute("INSERT INTO template(name, body) VALUES (?1, ?2) ON CONFLICT (name) DO \
+                      UPDATE SET body = ?2",
+                     params![name, body])?;
+        Ok(())
+    }
+
+    fn find_template(&self, name: &str) -> Result<Option<String>> {
+        let body = self.conn
+            .query_row("SELECT body FROM template WHERE name = ?1",
+                       params![name],
+                       |row| row.get(0))
+            .optional()?;
+        Ok(body)
+    }
+
+    fn all_templates(&self) -> Result<Vec<Template>> {
+        let mut stmt = self.conn.prepare("SELECT name, body FROM template ORDER BY name")?;
+        let rows = stmt.query_map(params![], |row| {
+                Ok(Template {
+                       name: row.get(0)?,
+                       body: row.get(1)?,
+                   })
+            })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+}
@@ -0,0 +1,92 @@
+use models::SearchResult;
+
+/// How many characters of context a snippet keeps around the first match
+const CONTEXT: usize = 30;
+
+fn lowercase(text: &str) -> Vec<char> {
+    text.chars().map(|c| c.to_lowercase().next().unwrap_or(c)).collect()
+}
+
+pub fn terms(query: &str) -> Vec<Vec<char>> {
+    query.split_whitespace().map(lowercase).collect()
+}
+
+/// The positions in `text` where `term` starts.
+fn occurrences(text: &[char], term: &[char]) -> Vec<usize> {
+    if term.len() > text.len() {
+        return vec![];
+    }
+    (0..text.len() - term.len() + 1).filter(|&i| &text[i..i + term.len()] == term).collect()
+}
+
+/// `None` unless every term occurs in `text`.
+pub fn rank(text: &str, terms: &[Vec<char>]) -> Option<f32> {
+    let text = lowercase(text);
+    let mut total = 0;
+    for term in terms {
+        let count = occurrences(&text, term).len();
+        if count == 0 {
+            return None;
+        }
+        total += count;
+    }
+    Some(total as f32)
+}
+
+/// A short excerpt around the first match, with the matched words surrounded by `*`.
+pub fn snippet(text: &str, terms: &[Vec<char>]) -> String {
+    let chars: Vec<char> = text.chars().map(|c| if c.is_whitespace() { ' ' } else { c }).collect();
+    let lower = lowercase(text);
+
+    let mut marked = vec![false; chars.len()];
+    for term in terms {
+        for start in occurrences(&lower, term) {
+            for flag in &mut marked[start..start + term.len()] {
+                *flag = true;
+            }
+        }
+    }
+    let first = marked.iter().position(|&flag| flag).unwrap_or(0);
+    let from = first.saturating_sub(CONTEXT);
+    let to = ::std::cmp::min(chars.len(), first + CONTEXT * 2);
+
+    let mut result = String::new();
+    if from > 0 {
+        result.push_str("...");
+    }
+    for i in from..to {
+        if marked[i] && (i == from || !marked[i - 1]) {
+            result.push('*');
+        }
+        result.push(chars[i]);
+        if marked[i] && (i + 1 == to || !marked[i + 1]) {
+            result.push('*');
+        }
+    }
+    if to < chars.len() {
+        result.push_str("...");
+    }
+    result
+}
+#[test]
+fn test_rank_and_snippet() {
+    let terms = terms("Fix bug");
+    assert_eq!(rank("Fixed a bug, then another bug", &terms), Some(3f32));
+    assert_eq!(rank("Fixed nothing", &terms), None);
+    assert_eq!(snippet("Fixed a BUG\nquickly", &terms), "*Fix*ed a *BUG* quickly");
+
+    let long = format!("{} needle {}", "x".repeat(40), "y".repeat(80));
+    let snippet = snippet(&long, &self::terms("needle"));
+    assert!(snippet.starts_with("...") && snippet.ends_with("..."));
+    assert!(snippet.contains(" *needle* "));
+}
+
+pub fn sort_results(results: &mut [SearchResult]) {
+    results.sort_by(|a, b| {
+        b.rank
+            .partial_cmp(&a.rank)
+            .unwrap_or(::std::cmp::Ordering::Equal)
+            .then(a.task_id.cmp(&b.task_id))
+            .then(a.note_id.cmp(&b.note_id))
+    });
+}
@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+
+use chrono::*;
+
+use error::Result;
+use models::Task;
+use store::Store;
+
+/// One billable note
+#[derive(Debug, Serialize)]
+pub struct TimesheetEntry {
+    pub task_id: i32,
+    /// Titles from the root task down to this one, separated by " / "
+    pub task_path: String,
+    pub note_id: i32,
+    #[serde(serialize_with = "::models::serialize_date")]
+    pub date_start: DateTime<Local>,
+    #[serde(serialize_with = "::models::serialize_date")]
+    pub date_end: DateTime<Local>,
+    pub duration_hours: f32,
+}
+
+/// The time spent on one task during one day, by the notes' start dates
+#[derive(Debug, Serialize)]
+pub struct TimesheetDay {
+    pub task_id: i32,
+    pub task_path: String,
+    pub date: String,
+    pub duration_hours: f32,
+}
+
+fn task_path(tasks: &HashMap<i32, Task>, id: i32) -> String {
+    let mut titles = vec![];
+    let mut current = tasks.get(&id);
+    while let Some(task) = current {
+        titles.push(task.title.as_str());
+        if titles.len() > tasks.len() {
+            break;
+        }
+        current = task.parent_id.and_then(|parent_id| tasks.get(&parent_id));
+    }
+    titles.reverse();
+    titles.join(" / ")
+}
+
+/// Notes started within `[from, to)`, ordered by task ID then start date.
+pub fn entries(store: &dyn Store,
+               from: DateTime<Local>,
+               to: DateTime<Local>)
+               -> Result<Vec<TimesheetEntry>> {
+    let mut tasks = HashMap::new();
+    for task in Task::all(store)? {
+        tasks.insert(task.id, task);
+    }
+    let mut paths: HashMap<i32, String> = HashMap::new();
+
+    let mut result = vec![];
+    for note in store.find_notes_between(from, to)? {
+        let path = paths.entry(note.task_id).or_insert_with(|| task_path(&tasks, note.task_id));
+        result.push(TimesheetEntry {
+                        task_id: note.task_id,
+                        task_path: path.clone(),
+                        note_id: note.id,
+                        date_start: note.date_start,
+                        date_end: note.date_end,
+                        duration_hours: note.duration_seconds / 3600f32,
+                    });
+    }
+    Ok(result)
+}
+
+/// Sums `entries` per task and per day, ordered by task ID then date.
+pub fn per_day(entries: &[TimesheetEntry]) -> Vec<TimesheetDay> {
+    let mut result: Vec<TimesheetDay> = vec![];
+    for entry in entries {
+        let date = entry.date_start.format("%Y-%m-%d").to_string();
+        match result.iter().position(|day| day.task_id == entry.task_id && day.date == date) {
+            Some(i) => result[i].duration_hours += entry.duration_hours,
+            None => {
+                result.push(TimesheetDay {
+                                task_id: entry.task_id,
+                                task_path: entry.task_path.clone(),
+                                date,
+                                duration_hours: entry.duration_hours,
+                            })
+            }
+        }
+    }
+    result.sort_by(|a, b| (a.task_id, &a.date).cmp(&(b.task_id, &b.date)));
+    result
+}
+#[test]
+fn test_entries_and_per_day() {
+    use models::Note;
+    use store::memory::MemoryStore;
+
+    let store = MemoryStore::new();
+    let start = Local.ymd(2017, 3, 1).and_hms(9, 0, 0);
+    Task::new(None, "Client", "", start).create(&store).unwrap();
+    Task::new(Some(1), "Feature", "", start).create(&store).unwrap();
+    Note::create(&store, 2, "a", "", start, start + Duration::minutes(90)).unwrap();
+    Note::create(&store, 2, "b", "", start + Duration::hours(3), start + Duration::hours(4))
+        .unwrap();
+    let next_day = start + Duration::days(1);
+    Note::create(&store, 1, "c", "", next_day, next_day + Duration::minutes(30)).unwrap();
+    Note::create(&store, 1, "old", "", start - Duration::days(1), start).unwrap();
+
+    let entries = entries(&store, start, start + Duration::days(2)).unwrap();
+    let rows: Vec<(i32, &str, f32)> = entries.iter()
+        .map(|e| (e.task_id, e.task_path.as_str(), e.duration_hours))
+        .collect();
+    assert_eq!(rows,
+               vec![(1, "Client", 0.5),
+                    (2, "Client / Feature", 1.5),
+                    (2, "Client / Feature", 1f32)]);
+
+    let days = per_day(&entries);
+    let days: Vec<(i32, &str, f32)> = days.iter()
+        .map(|d| (d.task_id, d.date.as_str(), d.duration_hours))
+        .collect();
+    assert_eq!(days, vec![(1, "2017-03-02", 0.5), (2, "2017-03-01", 2.5)]);
+}
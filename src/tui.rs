@@ -0,0 +1,609 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+use std::thread;
+use std::time::{Duration as StdDuration, Instant};
+
+use chrono::*;
+use termion;
+use termion::{clear, color, cursor, style};
+use termion::event::Key;
+use termion::input::TermRead;
+use termion::raw::IntoRawMode;
+use termion::screen::AlternateScreen;
+
+use error::{Error, Result};
+use models::{Task, TaskAux, Note, NoteAux, Timer, TreeNode, TreeOrder};
+use period;
+use render::{self, Renderer};
+use store::Store;
+
+/// How long to wait for a key before checking again
+const POLL_INTERVAL_MS: u64 = 50;
+/// How often to redraw without a key press, to keep the running timer's time current
+const TICK_SECONDS: u64 = 1;
+/// The tree pane's share of the screen width, in tenths
+const TREE_PANE_TENTHS: usize = 6;
+
+const HELP: &str = "j/k move  h/l fold  a subtask  A task  n note  s timer  f finish  \
+                    r reopen  m move  q quit";
+
+/// The task tree pane: every task, depth-first, with some subtrees folded away.
+pub struct TreeView {
+    nodes: Vec<TreeNode>,
+    collapsed: HashSet<i32>,
+    /// Position of the selected row among `visible()`
+    selected: usize,
+}
+
+impl TreeView {
+    pub fn new(nodes: Vec<TreeNode>) -> TreeView {
+        TreeView {
+            nodes,
+            collapsed: HashSet::new(),
+            selected: 0,
+        }
+    }
+
+    /// Replaces the nodes after a change, keeping the same task selected if it still exists.
+    pub fn reload(&mut self, nodes: Vec<TreeNode>) {
+        let selected_id = self.selected_node().map(|node| node.task.id);
+        let ids: HashSet<i32> = nodes.iter().map(|node| node.task.id).collect();
+        self.nodes = nodes;
+        self.collapsed.retain(|id| ids.contains(id));
+        match selected_id {
+            Some(id) if ids.contains(&id) => self.select_task(id),
+            _ => self.move_selection(0),
+        }
+    }
+
+    pub fn nodes(&self) -> &[TreeNode] {
+        &self.nodes
+    }
+
+    /// Indices into `nodes()` of the rows shown, leaving out those under a collapsed task
+    pub fn visible(&self) -> Vec<usize> {
+        let mut result = vec![];
+        let mut collapsed_depth = None;
+        for (i, node) in self.nodes.iter().enumerate() {
+            match collapsed_depth {
+                Some(depth) if node.depth > depth => continue,
+                _ => collapsed_depth = None,
+            }
+            result.push(i);
+            if self.collapsed.contains(&node.task.id) {
+                collapsed_depth = Some(node.depth);
+            }
+        }
+        result
+    }
+
+    pub fn has_children(&self, index: usize) -> bool {
+        self.nodes.get(index + 1).is_some_and(|next| next.depth > self.nodes[index].depth)
+    }
+
+    pub fn is_collapsed(&self, id: i32) -> bool {
+        self.collapsed.contains(&id)
+    }
+
+    /// Position of the selected row among `visible()`
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    pub fn selected_node(&self) -> Option<&TreeNode> {
+        self.visible().get(self.selected).map(|&i| &self.nodes[i])
+    }
+
+    /// Selects the task, unfolding its ancestors. Does nothing if it is not in the tree.
+    pub fn select_task(&mut self, id: i32) {
+        let path = match self.nodes.iter().find(|node| node.task.id == id) {
+            Some(node) => node.path.clone(),
+            None => return,
+        };
+        for ancestor in &path[..path.len() - 1] {
+            self.collapsed.remove(ancestor);
+        }
+        let visible = self.visible();
+        if let Some(position) = visible.iter().position(|&i| self.nodes[i].task.id == id) {
+            self.selected = position;
+        }
+    }
+
+    /// Moves the selection by `delta` rows, stopping at the first and last ones.
+    pub fn move_selection(&mut self, delta: i32) {
+        let last = self.visible().len() as i32 - 1;
+        self.selected = (self.selected as i32 + delta).min(last).max(0) as usize;
+    }
+
+    /// Folds the selected task, or selects its parent if it has nothing to fold.
+    pub fn collapse(&mut self) {
+        let (index, id, parent) = match self.visible().get(self.selected) {
+            Some(&i) => (i, self.nodes[i].task.id, self.nodes[i].task.parent_id),
+            None => return,
+        };
+        if self.has_children(index) && !self.collapsed.contains(&id) {
+            self.collapsed.insert(id);
+        } else if let Some(parent) = parent {
+            self.select_task(parent);
+        }
+    }
+
+    /// Unfolds the selected task, or moves down to its first child if it is already unfolded.
+    pub fn expand(&mut self) {
+        let (index, id) = match self.visible().get(self.selected) {
+            Some(&i) => (i, self.nodes[i].task.id),
+            None => return,
+        };
+        if !self.collapsed.remove(&id) && self.has_children(index) {
+            self.move_selection(1);
+        }
+    }
+
+    pub fn toggle(&mut self) {
+        let id = match self.selected_node() {
+            Some(node) => node.task.id,
+            None => return,
+        };
+        if !self.collapsed.remove(&id) {
+            self.collapse();
+        }
+    }
+}
+#[test]
+fn test_tree_view() {
+    let node = |id, parent_id, path: Vec<i32>| {
+        let mut task = Task::new(parent_id, "", "", Local::now());
+        task.id = id;
+        TreeNode {
+            task,
+            depth: path.len() as i32 - 1,
+            path,
+        }
+    };
+    let nodes = || {
+        vec![node(1, None, vec![1]),
+             node(2, Some(1), vec![1, 2]),
+             node(3, Some(2), vec![1, 2, 3]),
+             node(4, None, vec![4])]
+    };
+    let selected = |view: &TreeView| view.selected_node().map(|node| node.task.id);
+    let mut view = TreeView::new(nodes());
+    assert_eq!(view.visible(), vec![0, 1, 2, 3]);
+
+    view.move_selection(1);
+    view.collapse();
+    assert_eq!(view.visible(), vec![0, 1, 3]);
+    assert_eq!(selected(&view), Some(2));
+    view.collapse();
+    assert_eq!(selected(&view), Some(1));
+    view.toggle();
+    assert_eq!(view.visible(), vec![0, 3]);
+    view.move_selection(5);
+    assert_eq!(selected(&view), Some(4));
+
+    view.select_task(3);
+    assert_eq!((view.visible(), selected(&view)), (vec![0, 1, 2, 3], Some(3)));
+    view.collapse();
+    view.collapse();
+    view.reload(nodes().into_iter().filter(|node| node.task.id != 4).collect());
+    assert_eq!((view.visible(), selected(&view)), (vec![0, 1], Some(2)));
+    view.expand();
+    assert_eq!(view.visible(), vec![0, 1, 2]);
+    view.expand();
+    assert_eq!(selected(&view), Some(3));
+}
+
+/// What the text typed at the prompt is for
+enum PromptAction {
+    /// A new task's title, under this parent
+    NewTask(Option<i32>),
+    /// How long the work on a new note for this task took
+    NoteDuration(i32),
+    NoteBody(i32, Duration),
+    /// The note for the running timer
+    StopTimer,
+    /// The ID of this task's new parent
+    Move(i32),
+}
+
+struct Prompt {
+    action: PromptAction,
+    label: String,
+    input: String,
+}
+
+struct App<'a> {
+    store: &'a dyn Store,
+    tree: TreeView,
+    aux: HashMap<i32, TaskAux>,
+    /// The selected task's notes
+    notes: Vec<NoteAux>,
+    timer: Option<Timer>,
+    prompt: Option<Prompt>,
+    /// The outcome of the last action, shown in the status line
+    message: String,
+}
+
+/// `text` cut or padded with spaces to exactly `width` characters.
+fn fit(text: &str, width: usize) -> String {
+    let mut result: String = text.chars().take(width).collect();
+    let len = result.chars().count();
+    result.extend(std::iter::repeat_n(' ', width - len));
+    result
+}
+
+impl<'a> App<'a> {
+    fn new(store: &'a dyn Store) -> Result<App<'a>> {
+        let mut app = App {
+            store,
+            tree: TreeView::new(vec![]),
+            aux: HashMap::new(),
+            notes: vec![],
+            timer: None,
+            prompt: None,
+            message: String::new(),
+        };
+        app.reload()?;
+        Ok(app)
+    }
+
+    /// Reads the tree, the durations and the timer again after a change.
+    fn reload(&mut self) -> Result<()> {
+        self.tree.reload(Task::sorted_subtree(self.store, None, None, TreeOrder::Id)?);
+        self.aux = Task::all_aux(self.store)?.into_iter().map(|task| (task.id, task)).collect();
+        self.timer = Timer::active(self.store)?;
+        self.reload_notes()
+    }
+
+    fn reload_notes(&mut self) -> Result<()> {
+        self.notes = match self.tree.selected_node() {
+            Some(node) => Task::find_notes_aux(self.store, node.task.id)?,
+            None => vec![],
+        };
+        Ok(())
+    }
+
+    fn selected_task(&self) -> Result<&Task> {
+        let none = || Error::Validation("There are no tasks yet; press A to add one".to_owned());
+        self.tree.selected_node().map(|node| &node.task).ok_or_else(none)
+    }
+
+    fn ask(&mut self, action: PromptAction, label: String) {
+        self.prompt = Some(Prompt {
+                               action,
+                               label,
+                               input: String::new(),
+                           });
+    }
+
+    /// Handles a key, returning false to quit.
+    fn handle_key(&mut self, key: Key) -> Result<bool> {
+        if self.prompt.is_some() {
+            return self.handle_prompt_key(key).map(|_| true);
+        }
+        self.message.clear();
+        let selected_id = self.tree.selected_node().map(|node| node.task.id);
+        match key {
+            Key::Char('q') | Key::Ctrl('c') => return Ok(false),
+            Key::Char('j') | Key::Down => self.tree.move_selection(1),
+            Key::Char('k') | Key::Up => self.tree.move_selection(-1),
+            Key::PageDown => self.tree.move_selection(10),
+            Key::PageUp => self.tree.move_selection(-10),
+            Key::Char('h') | Key::Left => self.tree.collapse(),
+            Key::Char('l') | Key::Right => self.tree.expand(),
+            Key::Char(' ') | Key::Char('\n') => self.tree.toggle(),
+            Key::Char('A') => self.ask(PromptAction::NewTask(None), "New task".to_owned()),
+            Key::Char('a') => {
+                let id = self.selected_task()?.id;
+                self.ask(PromptAction::NewTask(Some(id)), format!("New subtask of {}", id));
+            }
+            Key::Char('n') => {
+                let id = self.selected_task()?.id;
+                self.ask(PromptAction::NoteDuration(id),
+                         format!("Time spent on {} for the new note, such as 1h30m", id));
+            }
+            Key::Char('s') => {
+                match self.timer {
+                    Some(ref timer) => {
+                        let label = format!("Note for the timer on {}", timer.task_id);
+                        self.ask(PromptAction::StopTimer, label);
+                    }
+                    None => {
+                        let id = self.selected_task()?.id;
+                        Timer::start(self.store, id)?;
+                        self.message = format!("Started a timer on task {}", id);
+                        self.reload()?;
+                    }
+                }
+            }
+            Key::Char('f') => {
+                let id = self.selected_task()?.id;
+                self.message = match Task::finish(self.store, id)? {
+                    Some(next) => format!("Task {} comes back as task {}", id, next.id),
+                    None => format!("Finished task {}", id),
+                };
+                self.reload()?;
+            }
+            Key::Char('r') => {
+                let id = self.selected_task()?.id;
+                Task::reopen(self.store, id)?;
+                self.message = format!("Reopened task {}", id);
+                self.reload()?;
+            }
+            Key::Char('m') => {
+                let id = self.selected_task()?.id;
+                self.ask(PromptAction::Move(id),
+                         format!("Move {} under task (empty for the top level)", id));
+            }
+            _ => {}
+        }
+        // moving around only changes which task's notes are shown
+        if self.tree.selected_node().map(|node| node.task.id) != selected_id {
+            self.reload_notes()?;
+        }
+        Ok(true)
+    }
+
+    fn handle_prompt_key(&mut self, key: Key) -> Result<()> {
+        match key {
+            Key::Esc | Key::Ctrl('c') => {
+                self.prompt = None;
+                Ok(())
+            }
+            Key::Backspace => {
+                if let Some(ref mut prompt) = self.prompt {
+                    prompt.input.pop();
+                }
+                Ok(())
+            }
+            Key::Char('\n') => {
+                match self.prompt.take() {
+                    Some(prompt) => self.submit(prompt.action, prompt.input.trim()),
+                    None => Ok(()),
+                }
+            }
+            Key::Char(c) => {
+                if let Some(ref mut prompt) = self.prompt {
+                    prompt.input.push(c);
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn submit(&mut self, action: PromptAction, input: &str) -> Result<()> {
+        let now = Local::now();
+        match action {
+            PromptAction::NewTask(parent_id) => {
+                if input.is_empty() {
+                    return Err(Error::Validation("A task needs a title".to_owned()));
+                }
+                let task = Task::new(parent_id, input, "", now).create(self.store)?;
+                self.reload()?;
+                self.tree.select_task(task.id);
+                self.message = format!("Created task {}", task.id);
+            }
+            PromptAction::NoteDuration(id) => {
+                if input.is_empty() {
+                    return Err(Error::Validation("A note needs the time spent".to_owned()));
+                }
+                let duration = period::parse_duration(input)?;
+                self.ask(PromptAction::NoteBody(id, duration), format!("Note on {}", id));
+                return Ok(());
+            }
+            PromptAction::NoteBody(id, duration) => {
+                let task_body = Task::find(self.store, id)?.body;
+                let note = Note::create(self.store, id, input, &task_body, now - duration, now)?;
+                self.message = format!("Added note {} to task {}", note.id, id);
+            }
+            PromptAction::StopTimer => {
+                let timer = Timer::active(self.store)?
+                    .ok_or_else(|| Error::Validation("No timer is running".to_owned()))?;
+                let task_body = Task::find(self.store, timer.task_id)?.body;
+                let note = Timer::stop(self.store, input, &task_body, now)?;
+                self.message = format!("Stopped the timer as note {}", note.id);
+            }
+            PromptAction::Move(id) => {
+                let parent_id = if input.is_empty() {
+                    None
+                } else {
+                    Some(input.parse()
+                        .map_err(|_| {
+                                     Error::Validation(format!("Expected a task ID, got {:?}",
+                                                               input))
+                                 })?)
+                };
+                Task::set_parent(self.store, id, parent_id)?;
+                self.message = format!("Moved task {}", id);
+            }
+        }
+        self.reload()
+    }
+
+    /// The path to the timed task and the seconds on its timer, if one is running
+    fn running(&self) -> Option<(&[i32], f32)> {
+        let timer = self.timer.as_ref()?;
+        let node = self.tree.nodes().iter().find(|node| node.task.id == timer.task_id)?;
+        let seconds = (Local::now() - timer.date_start).num_seconds() as f32;
+        Some((&node.path, seconds))
+    }
+
+    /// A task's own and total time, counting the running timer
+    fn durations(&self, id: i32) -> (f32, f32) {
+        let (mut own, mut total) = self.aux
+            .get(&id)
+            .map_or((0f32, 0f32),
+                    |aux| (aux.duration_seconds, aux.total_duration_seconds));
+        if let Some((path, seconds)) = self.running() {
+            if path.last() == Some(&id) {
+                own += seconds;
+            }
+            if path.contains(&id) {
+                total += seconds;
+            }
+        }
+        (own, total)
+    }
+
+    fn header(&self, width: usize) -> String {
+        let timer = match self.running() {
+            Some((path, seconds)) => {
+                let id = path[path.len() - 1];
+                let title = self.tree
+                    .nodes()
+                    .iter()
+                    .find(|node| node.task.id == id)
+                    .map_or("", |node| node.task.title.as_str());
+                format!("timer on {} {} for {}", id, title, render::duration(seconds).trim())
+            }
+            None => "no timer running".to_owned(),
+        };
+        format!("{}{}{}", style::Invert, fit(&format!(" todo  ·  {}", timer), width), style::Reset)
+    }
+
+    fn tree_lines(&self, width: usize, height: usize) -> Vec<String> {
+        let visible = self.tree.visible();
+        let nodes = self.tree.nodes();
+        let depths: Vec<usize> = visible.iter().map(|&i| nodes[i].depth as usize).collect();
+        let guides = render::tree_guides(&depths);
+        let id_width = render::id_width(visible.iter().map(|&i| nodes[i].task.id));
+        let plain = Renderer::plain(width);
+        let running_id = self.timer.as_ref().map(|timer| timer.task_id);
+
+        // scroll just enough to keep the selection on screen
+        let first = (self.tree.selected() + 1).saturating_sub(height);
+        let mut result = vec![];
+        for (position, &i) in visible.iter().enumerate().skip(first).take(height) {
+            let task = &nodes[i].task;
+            let fold = if !self.tree.has_children(i) {
+                "  "
+            } else if self.tree.is_collapsed(task.id) {
+                "▸ "
+            } else {
+                "▾ "
+            };
+            let line = format!("{}  {:>5$}  {}{}{}",
+                               render::duration(self.durations(task.id).1),
+                               task.id,
+                               guides[position],
+                               fold,
+                               plain.task_label(task),
+                               id_width);
+            let mut line = fit(&line, width);
+            if !task.open {
+                line = format!("{}{}{}", color::Fg(color::Green), line, color::Fg(color::Reset));
+            }
+            if Some(task.id) == running_id {
+                line = format!("{}{}{}", style::Bold, line, style::Reset);
+            }
+            if position == self.tree.selected() {
+                line = format!("{}{}{}", style::Invert, line, style::Reset);
+            }
+            result.push(line);
+        }
+        result
+    }
+
+    fn note_lines(&self, width: usize, height: usize) -> Vec<String> {
+        let task = match self.tree.selected_node() {
+            Some(node) => &node.task,
+            None => return vec![],
+        };
+        let (own, total) = self.durations(task.id);
+        let status = if task.open { "OPEN" } else { "DONE" };
+        let mut result = vec![format!("{}{}{}",
+                                      style::Bold,
+                                      fit(&format!("{}  {}", status, task.title), width),
+                                      style::Reset),
+                              fit(&format!("own {}, total {}",
+                                           render::duration(own).trim(),
+                                           render::duration(total).trim()),
+                                  width)];
+        let renderer = Renderer::plain(width);
+        if !task.body.trim().is_empty() {
+            result.push(String::new());
+            result.extend(renderer.wrap(&task.body, 0).lines().map(|line| fit(line, width)));
+        }
+        // newest first, so the most recent notes are the ones that fit
+        for note in self.notes.iter().rev() {
+            result.push(String::new());
+            let header = format!("note {}  {}  {}",
+                                 note.id,
+                                 render::duration(note.duration_seconds).trim(),
+                                 note.date_start.format("%Y-%m-%d %H:%M"));
+            result.push(format!("{}{}{}", style::Faint, fit(&header, width), style::Reset));
+            result.extend(renderer.wrap(&note.body, 2).lines().map(|line| fit(line, width)));
+        }
+        result.truncate(height);
+        result
+    }
+
+    fn draw<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let (width, height) = termion::terminal_size()?;
+        let (width, height) = (width as usize, height as usize);
+        let pane_height = height.saturating_sub(3);
+        let tree_width = width * TREE_PANE_TENTHS / 10;
+        let notes_width = width.saturating_sub(tree_width + 3);
+
+        let mut lines = vec![self.header(width)];
+        let tree = self.tree_lines(tree_width, pane_height);
+        let notes = self.note_lines(notes_width, pane_height);
+        let blank = fit("", tree_width);
+        for i in 0..pane_height {
+            lines.push(format!("{} │ {}",
+                               tree.get(i).unwrap_or(&blank),
+                               notes.get(i).map_or("", |line| line.as_str())));
+        }
+        let status = match self.prompt {
+            Some(ref prompt) => format!("{}: {}_", prompt.label, prompt.input),
+            None => self.message.clone(),
+        };
+        lines.push(fit(&status, width));
+        lines.push(format!("{}{}{}", style::Faint, fit(HELP, width), style::Reset));
+
+        for (y, line) in lines.iter().enumerate() {
+            write!(out, "{}{}{}", cursor::Goto(1, y as u16 + 1), line, clear::UntilNewline)?;
+        }
+        out.flush()
+    }
+}
+
+/// Runs the full-screen interface on the terminal until `q` is pressed. Errors from actions
+/// are shown in the status line rather than ending it.
+pub fn run(store: &dyn Store) -> Result<()> {
+    if !termion::is_tty(&io::stdout()) {
+        return Err(Error::Validation("The terminal UI needs stdout to be a terminal".to_owned()));
+    }
+    let mut app = App::new(store)?;
+    let mut screen = AlternateScreen::from(io::stdout().into_raw_mode()?);
+    write!(screen, "{}{}", cursor::Hide, clear::All)?;
+    let result = event_loop(&mut app, &mut screen);
+    write!(screen, "{}", cursor::Show)?;
+    screen.flush()?;
+    result
+}
+
+fn event_loop<W: Write>(app: &mut App, screen: &mut W) -> Result<()> {
+    let mut keys = termion::async_stdin().keys();
+    app.draw(screen)?;
+    let mut drawn = Instant::now();
+    loop {
+        match keys.next() {
+            Some(key) => {
+                match app.handle_key(key?) {
+                    Ok(true) => (),
+                    Ok(false) => return Ok(()),
+                    Err(err) => app.message = format!("Error: {}", err),
+                }
+            }
+            None if drawn.elapsed() >= StdDuration::from_secs(TICK_SECONDS) => (),
+            None => {
+                thread::sleep(StdDuration::from_millis(POLL_INTERVAL_MS));
+                continue;
+            }
+        }
+        app.draw(screen)?;
+        drawn = Instant::now();
+    }
+}
@@ -1,25 +1,14 @@
 use mktemp::Temp;
 
-use std;
+use error::{Error, Result};
+
 use std::env::var;
 use std::fs::{File, OpenOptions};
 use std::io::{Read, Write};
 use std::path::PathBuf;
 use std::process::Command;
 
-#[derive(Debug)]
-pub enum Error {
-    IO,
-    Subprocess,
-}
-
-impl From<std::io::Error> for Error {
-    fn from(_: std::io::Error) -> Error {
-        Error::IO
-    }
-}
-
-fn write_editor_template(path: PathBuf, template: &str) -> Result<(), Error> {
+fn write_editor_template(path: PathBuf, template: &str) -> Result<()> {
     let mut file = OpenOptions::new().write(true)
         .truncate(true)
         .open(&path)?;
@@ -28,7 +17,7 @@ fn write_editor_template(path: PathBuf, template: &str) -> Result<(), Error> {
     Ok(())
 }
 
-pub fn read_editor_input(template: &str) -> Result<String, Error> {
+pub fn read_editor_input(template: &str) -> Result<String> {
     let temp_file = Temp::new_file()?;
     let path = temp_file.to_path_buf();
 
@@ -43,6 +32,6 @@ pub fn read_editor_input(template: &str) -> Result<String, Error> {
         file.read_to_string(&mut s)?;
         Ok(s)
     } else {
-        Err(Error::Subprocess)
+        Err(Error::Subprocess(status_code))
     }
 }